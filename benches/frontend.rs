@@ -0,0 +1,123 @@
+//! Times the front end on large generated programs. Run with `cargo bench`.
+//!
+//! There's no benchmarking harness here, just `std::time`: each stage is run a few times and the
+//! fastest run is reported.
+//!
+//! Identifiers are interned, so the names pass copies and hashes `Symbol`s. The `names (strings)`
+//! line runs the same pass over copies of the names' text, as it was before interning, for
+//! comparison.
+
+use std::collections::HashSet;
+use std::fmt::Write;
+use std::time::{Duration, Instant};
+use tip::ast::{visit, Ident, Program, Visitor};
+use tip::cfg::IntraprocCFGBuilder;
+use tip::tip_parser;
+
+const RUNS: usize = 5;
+
+/// A program with `functions` functions, each declaring `vars` variables and then shuffling values
+/// between them in a loop, so that most of the source is identifiers.
+fn generate(functions: usize, vars: usize) -> String {
+    let mut src = String::new();
+    for f in 0..functions {
+        let names: Vec<_> = (0..vars).map(|v| format!("variable_{}", v)).collect();
+        writeln!(src, "function_{}(n) {{", f).unwrap();
+        writeln!(src, "    var {};", names.join(", ")).unwrap();
+        writeln!(src, "    while (n > 0) {{").unwrap();
+        for (idx, name) in names.iter().enumerate() {
+            let other = &names[(idx * 7 + 3) % vars];
+            writeln!(src, "        {} = {} + {} * n;", name, other, name).unwrap();
+            writeln!(
+                src,
+                "        if ({} > {}) {{ output {}; }}",
+                name, other, other
+            )
+            .unwrap();
+        }
+        writeln!(src, "        n = n - 1;").unwrap();
+        writeln!(src, "    }}").unwrap();
+        if f > 0 {
+            writeln!(src, "    return function_{}({});", f - 1, names[0]).unwrap();
+        } else {
+            writeln!(src, "    return {};", names[0]).unwrap();
+        }
+        writeln!(src, "}}").unwrap();
+    }
+    src
+}
+
+#[derive(Default)]
+struct Idents<'ast>(Vec<&'ast Ident>);
+
+impl<'ast> Visitor<'ast> for Idents<'ast> {
+    fn visit_ident(&mut self, id: &'ast Ident) {
+        self.0.push(id);
+    }
+}
+
+/// Counts the distinct names in each function, copying them into a set as a name resolution pass
+/// would.
+fn count_names(program: &Program) -> usize {
+    let mut total = 0;
+    for f in &program.functions {
+        let mut idents = Idents::default();
+        visit::walk_function(&mut idents, f);
+        let names: HashSet<_> = idents.0.iter().map(|id| id.name).collect();
+        total += names.len();
+    }
+    total
+}
+
+/// Like `count_names`, but copying each name's text into a `String`.
+fn count_names_as_strings(program: &Program) -> usize {
+    let mut total = 0;
+    for f in &program.functions {
+        let mut idents = Idents::default();
+        visit::walk_function(&mut idents, f);
+        let names: HashSet<String> = idents.0.iter().map(|id| id.name.to_string()).collect();
+        total += names.len();
+    }
+    total
+}
+
+fn best_of<T>(mut f: impl FnMut() -> T) -> (Duration, T) {
+    let mut best: Option<Duration> = None;
+    let mut result = None;
+    for _ in 0..RUNS {
+        let start = Instant::now();
+        result = Some(f());
+        let elapsed = start.elapsed();
+        best = Some(best.map_or(elapsed, |b| b.min(elapsed)));
+    }
+    (best.unwrap(), result.unwrap())
+}
+
+fn cfg_nodes(program: &Program) -> usize {
+    let cfgs = IntraprocCFGBuilder::from_program(program).to_owned_cfg_vec();
+    cfgs.iter().map(|c| c.graph().node_count()).sum()
+}
+
+fn main() {
+    for &(functions, vars) in &[(200, 50), (1000, 100)] {
+        let src = generate(functions, vars);
+        println!(
+            "{} functions, {} variables each ({} KiB)",
+            functions,
+            vars,
+            src.len() / 1024
+        );
+
+        let (time, program) = best_of(|| tip_parser::parse(src.clone()).unwrap());
+        println!("  parse: {:>10.2?}", time);
+
+        let (time, nodes) = best_of(|| cfg_nodes(&program));
+        println!("  cfg:   {:>10.2?} ({} nodes)", time, nodes);
+
+        let (time, names) = best_of(|| count_names(&program));
+        println!("  names: {:>10.2?} ({} distinct)", time, names);
+
+        let (time, names) = best_of(|| count_names_as_strings(&program));
+        println!("  names (strings): {:>10.2?} ({} distinct)", time, names);
+    }
+}
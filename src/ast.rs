@@ -1,9 +1,82 @@
+use crate::source_map::Span;
+use crate::symbol::Symbol;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicU32, Ordering};
+
+pub mod fold;
+#[cfg(feature = "serde")]
+pub mod json;
+pub mod visit;
+pub mod visit_mut;
+pub use fold::Folder;
+pub use visit::Visitor;
+pub use visit_mut::VisitorMut;
+
 pub type StatementList = Vec<Statement>;
 
-#[derive(Debug, PartialEq, Eq)]
-pub struct Ident(pub String);
+/// Identifies a single node in the AST, so that analyses can record facts about it in a `NodeMap`.
+///
+/// Every node gets a fresh id when it's created, and ids are never reused, so nodes from different
+/// files (or different parses of the same file) never share an id.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct NodeId(u32);
+
+impl NodeId {
+    pub fn fresh() -> NodeId {
+        static NEXT: AtomicU32 = AtomicU32::new(0);
+        NodeId(NEXT.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+/// A side table of facts about AST nodes.
+pub type NodeMap<T> = HashMap<NodeId, T>;
+
+// Spans and ids are deliberately left out of the equality checks for AST nodes: two nodes are equal
+// if they have the same structure, no matter where in the source they came from.
+//
+// With the `serde` feature, ids aren't serialised either: they're only unique within one run, so a
+// deserialised node gets a fresh one. Dummy spans are left out, and a missing span reads as a dummy.
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Ident {
+    pub name: Symbol,
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Span::is_dummy")
+    )]
+    pub span: Span,
+    #[cfg_attr(feature = "serde", serde(skip, default = "NodeId::fresh"))]
+    pub id: NodeId,
+}
+
+impl Ident {
+    pub fn new(name: impl Into<Symbol>, span: Span) -> Ident {
+        Ident {
+            name: name.into(),
+            span,
+            id: NodeId::fresh(),
+        }
+    }
+}
+
+impl From<&str> for Ident {
+    fn from(name: &str) -> Ident {
+        Ident::new(name, Span::DUMMY)
+    }
+}
+
+impl PartialEq for Ident {
+    fn eq(&self, other: &Ident) -> bool {
+        self.name == other.name
+    }
+}
+impl Eq for Ident {}
 
 #[derive(Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum BinOp {
     Plus,
     Minus,
@@ -14,18 +87,57 @@ pub enum BinOp {
 }
 
 #[derive(Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum UnOp {
     Negate,
     AddressOf,
     Dereference,
 }
 
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Expression {
+    pub kind: ExpressionKind,
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Span::is_dummy")
+    )]
+    pub span: Span,
+    #[cfg_attr(feature = "serde", serde(skip, default = "NodeId::fresh"))]
+    pub id: NodeId,
+}
+
+impl Expression {
+    pub fn new(kind: ExpressionKind, span: Span) -> Expression {
+        Expression {
+            kind,
+            span,
+            id: NodeId::fresh(),
+        }
+    }
+}
+
+impl From<ExpressionKind> for Expression {
+    fn from(kind: ExpressionKind) -> Expression {
+        Expression::new(kind, Span::DUMMY)
+    }
+}
+
+impl PartialEq for Expression {
+    fn eq(&self, other: &Expression) -> bool {
+        self.kind == other.kind
+    }
+}
+impl Eq for Expression {}
+
 #[derive(Debug, PartialEq, Eq)]
-pub enum Expression {
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum ExpressionKind {
     Number(i64),
     BinaryExpression(BinOp, Box<Expression>, Box<Expression>),
     IdentReference(Ident),
     Input,
+    Null,
     Call(Box<Expression>, Vec<Box<Expression>>),
     UnaryExpression(UnOp, Box<Expression>),
     Alloc(Box<Expression>),
@@ -33,9 +145,46 @@ pub enum Expression {
     Projection(Box<Expression>, Vec<Ident>),
 }
 
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Statement {
+    pub kind: StatementKind,
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Span::is_dummy")
+    )]
+    pub span: Span,
+    #[cfg_attr(feature = "serde", serde(skip, default = "NodeId::fresh"))]
+    pub id: NodeId,
+}
+
+impl Statement {
+    pub fn new(kind: StatementKind, span: Span) -> Statement {
+        Statement {
+            kind,
+            span,
+            id: NodeId::fresh(),
+        }
+    }
+}
+
+impl From<StatementKind> for Statement {
+    fn from(kind: StatementKind) -> Statement {
+        Statement::new(kind, Span::DUMMY)
+    }
+}
+
+impl PartialEq for Statement {
+    fn eq(&self, other: &Statement) -> bool {
+        self.kind == other.kind
+    }
+}
+impl Eq for Statement {}
+
 #[derive(Debug, PartialEq, Eq)]
-pub enum Statement {
-    VarDecl(Vec<Ident>), // TODO: Intern strings?
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum StatementKind {
+    VarDecl(Vec<Ident>),
     Assign(Expression, Expression),
     If {
         cond: Expression,
@@ -52,46 +201,134 @@ pub enum Statement {
     Error(Expression),
     ExpressionStatement(Expression),
     Block(StatementList),
+    /// Placeholder for source that failed to parse, left behind when the parser recovers from a
+    /// syntax error.
+    Invalid,
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Function {
     pub name: Ident,
     pub params: Vec<Ident>,
     pub body: StatementList,
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Span::is_dummy")
+    )]
+    pub span: Span,
+    #[cfg_attr(feature = "serde", serde(skip, default = "NodeId::fresh"))]
+    pub id: NodeId,
 }
 
+impl Function {
+    pub fn new(name: Ident, params: Vec<Ident>, body: StatementList, span: Span) -> Function {
+        Function {
+            name,
+            params,
+            body,
+            span,
+            id: NodeId::fresh(),
+        }
+    }
+}
+
+impl PartialEq for Function {
+    fn eq(&self, other: &Function) -> bool {
+        self.name == other.name && self.params == other.params && self.body == other.body
+    }
+}
+impl Eq for Function {}
+
 #[derive(Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Program {
     pub functions: Vec<Function>,
 }
 
-pub trait ASTVisitor {
-    fn visit_program(&mut self, p: Program) {
-        super_visit_program(self, p)
-    }
-    fn visit_function(&mut self, f: Function) {
-        super_visit_function(self, f)
-    }
+/// A `//` or `/* */` comment, including its delimiters.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Comment {
+    pub text: String,
+    pub span: Span,
+}
 
-    fn visit_statement_list(&mut self, s: StatementList) {
-        super_visit_statement_list(self, s)
+impl Comment {
+    /// Whether this is a `//` comment, which runs to the end of its line.
+    pub fn is_line_comment(&self) -> bool {
+        self.text.starts_with("//")
     }
-    fn visit_statement(&mut self, s: Statement);
 }
 
-pub fn super_visit_program(vis: &mut (impl ASTVisitor + ?Sized), p: Program) {
-    for f in p.functions {
-        vis.visit_function(f)
-    }
+/// Which of a node's statement lists a comment belongs to. Only `if` statements have more than one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Body {
+    Main,
+    Else,
 }
 
-pub fn super_visit_function(vis: &mut (impl ASTVisitor + ?Sized), f: Function) {
-    vis.visit_statement_list(f.body)
+/// The comments in a file, attached to the statements and functions around them so that a program
+/// can be printed back out without losing them.
+///
+/// Unlike the rest of the AST, trivia can't be serialised, since it's keyed by node ids.
+#[derive(Debug, Default)]
+pub struct Trivia {
+    /// Comments on lines of their own before a statement or function.
+    pub leading: NodeMap<Vec<Comment>>,
+    /// Comments on the line a statement or function ends on. For statements without a body, this
+    /// includes comments from inside the statement.
+    pub trailing: NodeMap<Vec<Comment>>,
+    /// Comments on the line opening one of a node's bodies, after its `{`. For the main body, this
+    /// includes comments from the node's header, eg. inside the condition of an `if`.
+    pub body_start: HashMap<(NodeId, Body), Vec<Comment>>,
+    /// Comments after the last statement of one of a node's bodies.
+    pub body_end: HashMap<(NodeId, Body), Vec<Comment>>,
+    /// Comments after the last function in the file.
+    pub end: Vec<Comment>,
+    /// Statements separated from the statement before them by a blank line.
+    pub blank_line_before: HashSet<NodeId>,
 }
 
-pub fn super_visit_statement_list(vis: &mut (impl ASTVisitor + ?Sized), sl: StatementList) {
-    for s in sl {
-        vis.visit_statement(s)
+#[cfg(test)]
+mod tests {
+    use super::visit::{self, Visitor};
+    use super::*;
+    use crate::tip_parser;
+
+    #[derive(Default)]
+    struct NodeIds(Vec<NodeId>);
+
+    impl<'ast> Visitor<'ast> for NodeIds {
+        fn visit_function(&mut self, f: &'ast Function) {
+            self.0.push(f.id);
+            visit::walk_function(self, f);
+        }
+        fn visit_statement(&mut self, s: &'ast Statement) {
+            self.0.push(s.id);
+            visit::walk_statement(self, s);
+        }
+        fn visit_expression(&mut self, e: &'ast Expression) {
+            self.0.push(e.id);
+            visit::walk_expression(self, e);
+        }
+        fn visit_ident(&mut self, id: &'ast Ident) {
+            self.0.push(id.id);
+        }
+        fn visit_field(&mut self, id: &'ast Ident) {
+            self.0.push(id.id);
+        }
+    }
+
+    #[test]
+    fn test_node_ids_are_unique_across_parses() {
+        let src = "f(x) { var y; y = {a: x + 1}; return y.a; }";
+        let mut ids = NodeIds::default();
+        ids.visit_program(&tip_parser::parse(src.to_string()).unwrap());
+        ids.visit_program(&tip_parser::parse(src.to_string()).unwrap());
+        let unique: HashSet<_> = ids.0.iter().collect();
+        assert_eq!(ids.0.len(), 38);
+        assert_eq!(unique.len(), ids.0.len());
     }
 }
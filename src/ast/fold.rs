@@ -0,0 +1,174 @@
+//! Rebuilding the AST. Each `fold_*` method takes a node by value and returns its replacement; the
+//! `walk_*` functions rebuild a node from its folded children, keeping its span and id.
+
+use super::{Expression, ExpressionKind, Function, Ident, Program, Statement, StatementKind};
+
+pub trait Folder {
+    fn fold_program(&mut self, p: Program) -> Program {
+        walk_program(self, p)
+    }
+    fn fold_function(&mut self, f: Function) -> Function {
+        walk_function(self, f)
+    }
+    fn fold_statement(&mut self, s: Statement) -> Statement {
+        walk_statement(self, s)
+    }
+    fn fold_expression(&mut self, e: Expression) -> Expression {
+        walk_expression(self, e)
+    }
+    /// Called for the names of functions and variables, wherever they are declared or used.
+    fn fold_ident(&mut self, id: Ident) -> Ident {
+        id
+    }
+    /// Called for the names of record fields, in record literals and projections.
+    fn fold_field(&mut self, id: Ident) -> Ident {
+        id
+    }
+}
+
+pub fn walk_program<F: Folder + ?Sized>(folder: &mut F, p: Program) -> Program {
+    Program {
+        functions: p
+            .functions
+            .into_iter()
+            .map(|f| folder.fold_function(f))
+            .collect(),
+    }
+}
+
+pub fn walk_function<F: Folder + ?Sized>(folder: &mut F, f: Function) -> Function {
+    Function {
+        name: folder.fold_ident(f.name),
+        params: f.params.into_iter().map(|p| folder.fold_ident(p)).collect(),
+        body: walk_statements(folder, f.body),
+        span: f.span,
+        id: f.id,
+    }
+}
+
+fn walk_statements<F: Folder + ?Sized>(folder: &mut F, stmts: Vec<Statement>) -> Vec<Statement> {
+    stmts
+        .into_iter()
+        .map(|s| folder.fold_statement(s))
+        .collect()
+}
+
+pub fn walk_statement<F: Folder + ?Sized>(folder: &mut F, s: Statement) -> Statement {
+    let kind = match s.kind {
+        StatementKind::VarDecl(ids) => {
+            StatementKind::VarDecl(ids.into_iter().map(|id| folder.fold_ident(id)).collect())
+        }
+        StatementKind::Assign(lhs, rhs) => {
+            StatementKind::Assign(folder.fold_expression(lhs), folder.fold_expression(rhs))
+        }
+        StatementKind::If {
+            cond,
+            then,
+            otherwise,
+        } => StatementKind::If {
+            cond: folder.fold_expression(cond),
+            then: then.map(|then| walk_statements(folder, then)),
+            otherwise: otherwise.map(|otherwise| walk_statements(folder, otherwise)),
+        },
+        StatementKind::While { cond, then } => StatementKind::While {
+            cond: folder.fold_expression(cond),
+            then: then.map(|then| walk_statements(folder, then)),
+        },
+        StatementKind::Output(e) => StatementKind::Output(folder.fold_expression(e)),
+        StatementKind::Return(e) => StatementKind::Return(e.map(|e| folder.fold_expression(e))),
+        StatementKind::Error(e) => StatementKind::Error(folder.fold_expression(e)),
+        StatementKind::ExpressionStatement(e) => {
+            StatementKind::ExpressionStatement(folder.fold_expression(e))
+        }
+        StatementKind::Block(body) => StatementKind::Block(walk_statements(folder, body)),
+        kind @ (StatementKind::Break | StatementKind::Invalid) => kind,
+    };
+    Statement {
+        kind,
+        span: s.span,
+        id: s.id,
+    }
+}
+
+pub fn walk_expression<F: Folder + ?Sized>(folder: &mut F, e: Expression) -> Expression {
+    let kind = match e.kind {
+        kind @ (ExpressionKind::Number(_) | ExpressionKind::Input | ExpressionKind::Null) => kind,
+        ExpressionKind::IdentReference(id) => ExpressionKind::IdentReference(folder.fold_ident(id)),
+        ExpressionKind::BinaryExpression(op, l, r) => {
+            ExpressionKind::BinaryExpression(op, fold_boxed(folder, l), fold_boxed(folder, r))
+        }
+        ExpressionKind::Call(f, args) => {
+            let f = fold_boxed(folder, f);
+            let args = args.into_iter().map(|a| fold_boxed(folder, a)).collect();
+            ExpressionKind::Call(f, args)
+        }
+        ExpressionKind::UnaryExpression(op, operand) => {
+            ExpressionKind::UnaryExpression(op, fold_boxed(folder, operand))
+        }
+        ExpressionKind::Alloc(operand) => ExpressionKind::Alloc(fold_boxed(folder, operand)),
+        ExpressionKind::Record(fields) => ExpressionKind::Record(
+            fields
+                .into_iter()
+                .map(|(id, e)| (folder.fold_field(id), folder.fold_expression(e)))
+                .collect(),
+        ),
+        ExpressionKind::Projection(record, fields) => {
+            let record = fold_boxed(folder, record);
+            ExpressionKind::Projection(
+                record,
+                fields.into_iter().map(|id| folder.fold_field(id)).collect(),
+            )
+        }
+    };
+    Expression {
+        kind,
+        span: e.span,
+        id: e.id,
+    }
+}
+
+/// Folds a boxed expression, reusing its allocation.
+fn fold_boxed<F: Folder + ?Sized>(folder: &mut F, mut e: Box<Expression>) -> Box<Expression> {
+    *e = folder.fold_expression(*e);
+    e
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::BinOp;
+    use crate::tip_parser;
+
+    /// Evaluates arithmetic on constants.
+    struct ConstantFolder;
+
+    impl Folder for ConstantFolder {
+        fn fold_expression(&mut self, e: Expression) -> Expression {
+            let e = walk_expression(self, e);
+            if let ExpressionKind::BinaryExpression(op, l, r) = &e.kind {
+                if let (ExpressionKind::Number(l), ExpressionKind::Number(r)) = (&l.kind, &r.kind) {
+                    let n = match op {
+                        BinOp::Plus => l + r,
+                        BinOp::Minus => l - r,
+                        BinOp::Times => l * r,
+                        _ => return e,
+                    };
+                    return Expression::new(ExpressionKind::Number(n), e.span);
+                }
+            }
+            e
+        }
+    }
+
+    #[test]
+    fn test_constant_folding() {
+        let parse = |src: &str| tip_parser::parse(src.to_string()).unwrap();
+        let program = parse(
+            "main() { var x; if (x > 1 + 1) { x = {f: 2 * 3}; } else { output f(4 - 1).g; } return x; }",
+        );
+        assert_eq!(
+            ConstantFolder.fold_program(program),
+            parse("main() { var x; if (x > 2) { x = {f: 6}; } else { output f(3).g; } return x; }")
+        );
+    }
+}
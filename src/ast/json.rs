@@ -0,0 +1,82 @@
+//! The AST as JSON, for tools that want a TIP program without parsing TIP, and for test fixtures
+//! written without the parser.
+//!
+//! Spans are offsets into the `SourceMap` the program was parsed with, so they're only meaningful
+//! alongside the same files loaded in the same order. Nodes built without source have no span in
+//! the JSON, and a node with no span is read back with a dummy one.
+
+use super::Program;
+
+/// `program` as pretty-printed JSON.
+pub fn to_string(program: &Program) -> String {
+    serde_json::to_string_pretty(program).expect("the AST is always serialisable")
+}
+
+/// Reads a program written by `to_string`, or by hand in the same format. Every node gets a fresh
+/// id.
+pub fn from_str(json: &str) -> serde_json::Result<Program> {
+    serde_json::from_str(json)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::{BinOp, ExpressionKind, StatementKind};
+    use crate::source_map::Span;
+    use crate::tip_parser;
+
+    #[test]
+    fn test_round_trip() {
+        for entry in std::fs::read_dir("examples").unwrap() {
+            let path = entry.unwrap().path();
+            let src = std::fs::read_to_string(&path).unwrap();
+            let program = match tip_parser::parse(src) {
+                Ok(program) => program,
+                Err(_) => continue,
+            };
+            let json = to_string(&program);
+            let loaded = from_str(&json).unwrap();
+            assert_eq!(loaded, program, "round-tripping {}", path.display());
+            assert_eq!(to_string(&loaded), json);
+            assert_ne!(loaded.functions[0].id, program.functions[0].id);
+        }
+    }
+
+    #[test]
+    fn test_handwritten() {
+        let program = from_str(
+            r#"{
+                "functions": [{
+                    "name": { "name": "main" },
+                    "params": [{ "name": "x", "span": { "lo": 5, "hi": 6 } }],
+                    "body": [
+                        { "kind": { "Return": {
+                            "kind": { "BinaryExpression": [
+                                "Plus",
+                                { "kind": { "IdentReference": { "name": "x" } } },
+                                { "kind": { "Number": 1 } }
+                            ] }
+                        } } }
+                    ]
+                }]
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            program,
+            tip_parser::parse("main(x) { return x + 1; }".to_string()).unwrap()
+        );
+        let f = &program.functions[0];
+        assert_eq!(f.span, Span::DUMMY);
+        assert_eq!(f.params[0].span, Span::new(5, 6));
+        match &f.body[0].kind {
+            StatementKind::Return(Some(e)) => assert!(matches!(
+                e.kind,
+                ExpressionKind::BinaryExpression(BinOp::Plus, ..)
+            )),
+            s => panic!("expected a return, got {:?}", s),
+        }
+
+        assert!(from_str(r#"{ "functions": [{ "name": "main" }] }"#).is_err());
+    }
+}
@@ -0,0 +1,153 @@
+//! Read-only traversal of the AST.
+//!
+//! Each `visit_*` method defaults to the matching `walk_*` function, which visits the node's
+//! children. Override a method to act on one kind of node, calling its `walk_*` function from the
+//! override to keep descending.
+
+use super::{Expression, ExpressionKind, Function, Ident, Program, Statement, StatementKind};
+
+pub trait Visitor<'ast> {
+    fn visit_program(&mut self, p: &'ast Program) {
+        walk_program(self, p)
+    }
+    fn visit_function(&mut self, f: &'ast Function) {
+        walk_function(self, f)
+    }
+    fn visit_statement(&mut self, s: &'ast Statement) {
+        walk_statement(self, s)
+    }
+    fn visit_expression(&mut self, e: &'ast Expression) {
+        walk_expression(self, e)
+    }
+    /// Called for the names of functions and variables, wherever they are declared or used.
+    fn visit_ident(&mut self, _id: &'ast Ident) {}
+    /// Called for the names of record fields, in record literals and projections.
+    fn visit_field(&mut self, _id: &'ast Ident) {}
+}
+
+pub fn walk_program<'ast, V: Visitor<'ast> + ?Sized>(v: &mut V, p: &'ast Program) {
+    for f in &p.functions {
+        v.visit_function(f);
+    }
+}
+
+pub fn walk_function<'ast, V: Visitor<'ast> + ?Sized>(v: &mut V, f: &'ast Function) {
+    v.visit_ident(&f.name);
+    for param in &f.params {
+        v.visit_ident(param);
+    }
+    walk_statements(v, &f.body);
+}
+
+fn walk_statements<'ast, V: Visitor<'ast> + ?Sized>(v: &mut V, stmts: &'ast [Statement]) {
+    for s in stmts {
+        v.visit_statement(s);
+    }
+}
+
+pub fn walk_statement<'ast, V: Visitor<'ast> + ?Sized>(v: &mut V, s: &'ast Statement) {
+    match &s.kind {
+        StatementKind::VarDecl(ids) => {
+            for id in ids {
+                v.visit_ident(id);
+            }
+        }
+        StatementKind::Assign(lhs, rhs) => {
+            v.visit_expression(lhs);
+            v.visit_expression(rhs);
+        }
+        StatementKind::If {
+            cond,
+            then,
+            otherwise,
+        } => {
+            v.visit_expression(cond);
+            walk_statements(v, then.as_deref().unwrap_or_default());
+            walk_statements(v, otherwise.as_deref().unwrap_or_default());
+        }
+        StatementKind::While { cond, then } => {
+            v.visit_expression(cond);
+            walk_statements(v, then.as_deref().unwrap_or_default());
+        }
+        StatementKind::Output(e)
+        | StatementKind::Return(Some(e))
+        | StatementKind::Error(e)
+        | StatementKind::ExpressionStatement(e) => v.visit_expression(e),
+        StatementKind::Block(body) => walk_statements(v, body),
+        StatementKind::Break | StatementKind::Return(None) | StatementKind::Invalid => {}
+    }
+}
+
+pub fn walk_expression<'ast, V: Visitor<'ast> + ?Sized>(v: &mut V, e: &'ast Expression) {
+    match &e.kind {
+        ExpressionKind::Number(_) | ExpressionKind::Input | ExpressionKind::Null => {}
+        ExpressionKind::IdentReference(id) => v.visit_ident(id),
+        ExpressionKind::BinaryExpression(_, l, r) => {
+            v.visit_expression(l);
+            v.visit_expression(r);
+        }
+        ExpressionKind::Call(f, args) => {
+            v.visit_expression(f);
+            for arg in args {
+                v.visit_expression(arg);
+            }
+        }
+        ExpressionKind::UnaryExpression(_, operand) | ExpressionKind::Alloc(operand) => {
+            v.visit_expression(operand)
+        }
+        ExpressionKind::Record(fields) => {
+            for (id, e) in fields {
+                v.visit_field(id);
+                v.visit_expression(e);
+            }
+        }
+        ExpressionKind::Projection(record, fields) => {
+            v.visit_expression(record);
+            for id in fields {
+                v.visit_field(id);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tip_parser;
+
+    #[derive(Default)]
+    struct Names<'ast> {
+        idents: Vec<&'ast str>,
+        fields: Vec<&'ast str>,
+    }
+
+    impl<'ast> Visitor<'ast> for Names<'ast> {
+        fn visit_ident(&mut self, id: &'ast Ident) {
+            self.idents.push(id.name.as_str());
+        }
+        fn visit_field(&mut self, id: &'ast Ident) {
+            self.fields.push(id.name.as_str());
+        }
+    }
+
+    #[test]
+    fn test_visits_every_name() {
+        let program = tip_parser::parse(
+            "\
+f(a) {
+    var b;
+    if (a > 0) { b = {x: a}; } else { while (a) { output g(a, *b.y); } }
+    { return b.x; }
+}"
+            .to_string(),
+        )
+        .unwrap();
+        let mut names = Names::default();
+        names.visit_program(&program);
+        assert_eq!(
+            names.idents,
+            ["f", "a", "b", "a", "b", "a", "a", "g", "a", "b", "b"]
+        );
+        assert_eq!(names.fields, ["x", "y", "x"]);
+    }
+}
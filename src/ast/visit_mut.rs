@@ -0,0 +1,137 @@
+//! Traversal of the AST that can change nodes in place. Works like `visit`, but with mutable
+//! references.
+
+use super::{Expression, ExpressionKind, Function, Ident, Program, Statement, StatementKind};
+
+pub trait VisitorMut {
+    fn visit_program(&mut self, p: &mut Program) {
+        walk_program(self, p)
+    }
+    fn visit_function(&mut self, f: &mut Function) {
+        walk_function(self, f)
+    }
+    fn visit_statement(&mut self, s: &mut Statement) {
+        walk_statement(self, s)
+    }
+    fn visit_expression(&mut self, e: &mut Expression) {
+        walk_expression(self, e)
+    }
+    /// Called for the names of functions and variables, wherever they are declared or used.
+    fn visit_ident(&mut self, _id: &mut Ident) {}
+    /// Called for the names of record fields, in record literals and projections.
+    fn visit_field(&mut self, _id: &mut Ident) {}
+}
+
+pub fn walk_program<V: VisitorMut + ?Sized>(v: &mut V, p: &mut Program) {
+    for f in &mut p.functions {
+        v.visit_function(f);
+    }
+}
+
+pub fn walk_function<V: VisitorMut + ?Sized>(v: &mut V, f: &mut Function) {
+    v.visit_ident(&mut f.name);
+    for param in &mut f.params {
+        v.visit_ident(param);
+    }
+    walk_statements(v, &mut f.body);
+}
+
+fn walk_statements<V: VisitorMut + ?Sized>(v: &mut V, stmts: &mut [Statement]) {
+    for s in stmts {
+        v.visit_statement(s);
+    }
+}
+
+pub fn walk_statement<V: VisitorMut + ?Sized>(v: &mut V, s: &mut Statement) {
+    match &mut s.kind {
+        StatementKind::VarDecl(ids) => {
+            for id in ids {
+                v.visit_ident(id);
+            }
+        }
+        StatementKind::Assign(lhs, rhs) => {
+            v.visit_expression(lhs);
+            v.visit_expression(rhs);
+        }
+        StatementKind::If {
+            cond,
+            then,
+            otherwise,
+        } => {
+            v.visit_expression(cond);
+            walk_statements(v, then.as_deref_mut().unwrap_or_default());
+            walk_statements(v, otherwise.as_deref_mut().unwrap_or_default());
+        }
+        StatementKind::While { cond, then } => {
+            v.visit_expression(cond);
+            walk_statements(v, then.as_deref_mut().unwrap_or_default());
+        }
+        StatementKind::Output(e)
+        | StatementKind::Return(Some(e))
+        | StatementKind::Error(e)
+        | StatementKind::ExpressionStatement(e) => v.visit_expression(e),
+        StatementKind::Block(body) => walk_statements(v, body),
+        StatementKind::Break | StatementKind::Return(None) | StatementKind::Invalid => {}
+    }
+}
+
+pub fn walk_expression<V: VisitorMut + ?Sized>(v: &mut V, e: &mut Expression) {
+    match &mut e.kind {
+        ExpressionKind::Number(_) | ExpressionKind::Input | ExpressionKind::Null => {}
+        ExpressionKind::IdentReference(id) => v.visit_ident(id),
+        ExpressionKind::BinaryExpression(_, l, r) => {
+            v.visit_expression(l);
+            v.visit_expression(r);
+        }
+        ExpressionKind::Call(f, args) => {
+            v.visit_expression(f);
+            for arg in args {
+                v.visit_expression(arg);
+            }
+        }
+        ExpressionKind::UnaryExpression(_, operand) | ExpressionKind::Alloc(operand) => {
+            v.visit_expression(operand)
+        }
+        ExpressionKind::Record(fields) => {
+            for (id, e) in fields {
+                v.visit_field(id);
+                v.visit_expression(e);
+            }
+        }
+        ExpressionKind::Projection(record, fields) => {
+            v.visit_expression(record);
+            for id in fields {
+                v.visit_field(id);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::symbol::Symbol;
+    use crate::tip_parser;
+
+    struct Rename;
+
+    impl VisitorMut for Rename {
+        fn visit_ident(&mut self, id: &mut Ident) {
+            if id.name == "x" {
+                id.name = Symbol::intern("y");
+            }
+        }
+    }
+
+    #[test]
+    fn test_rename_in_place() {
+        let parse = |src: &str| tip_parser::parse(src.to_string()).unwrap();
+        let mut program =
+            parse("f(x) { var z; while (x > 0) { if (x) { z = {x: x}; } } return z.x; }");
+        Rename.visit_program(&mut program);
+        assert_eq!(
+            program,
+            parse("f(y) { var z; while (y > 0) { if (y) { z = {x: y}; } } return z.x; }")
+        );
+    }
+}
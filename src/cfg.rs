@@ -1,86 +1,542 @@
-use crate::ast::{super_visit_function, ASTVisitor, Expression, Function, Program, Statement};
-use petgraph::graph::NodeIndex;
+use crate::ast::visit::Visitor;
+use crate::ast::{Expression, Function, Program, Statement, StatementKind, StatementList};
+use petgraph::graph::{DiGraph, NodeIndex};
+use petgraph::visit::{Dfs, EdgeRef};
+use petgraph::Direction;
+use std::collections::HashMap;
+use std::fmt;
+use std::mem;
+use std::ops::Index;
 
-pub type Cfg = petgraph::graph::DiGraph<CFGNode, EdgeCondition>;
+pub mod blocks;
+pub mod dominance;
+pub mod interproc;
+pub use blocks::{BasicBlock, BlockCfg, BlockEnd};
+pub use interproc::{InterprocCFGBuilder, InterprocCfg};
 
-#[derive(Debug)]
-// FIXME: This is pretty clumsy. It means that any number of edges can be added to any kind of cfg node.
-// Ideally, we'd want to be able to let the Cfg nodes be responsible for their own edges -- that way, for
-// example, we can assert that there's at most a single true and false edge coming out from an if statement
-// CFG node.
+/// A function's control flow graph, with a node per statement or branch.
+///
+/// A node's successors are given by its `Terminator`, which must suit the kind of node: `Entry` and
+/// `Statement` nodes go to a single successor, `CondBr` nodes branch to two, and `Exit` has none.
+/// Edges can only be added by setting a node's terminator, and `verify` checks that every node's
+/// terminator suits it, along with the rest of the graph's structure.
+#[derive(Debug, Default)]
+pub struct Cfg<'ast> {
+    graph: DiGraph<CFGNode<'ast>, EdgeCondition>,
+}
+
+/// The condition under which an edge of `Cfg::graph` is taken.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum EdgeCondition {
     Unconditional,
     IfTrue,
     IfFalse,
 }
 
-#[derive(Debug)]
-pub enum CFGNode {
+/// A node in a CFG. Nodes refer to the AST rather than owning parts of it, so the AST must outlive
+/// the CFG; use the nodes' `NodeId`s to look up facts about them.
+#[derive(Debug, Clone, Copy)]
+pub enum CFGNode<'ast> {
     Entry,
-    Statement(Statement),
-    CondBr(Expression),
+    Statement(&'ast Statement),
+    CondBr(&'ast Expression),
+    Exit,
+}
+
+/// Shows a node as its statement or, for a branch, its condition in brackets.
+impl fmt::Display for CFGNode<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CFGNode::Entry => f.write_str("entry"),
+            CFGNode::Statement(s) => s.fmt(f),
+            CFGNode::CondBr(e) => write!(f, "[{}]", e),
+            CFGNode::Exit => f.write_str("exit"),
+        }
+    }
+}
+
+/// Where control goes after a node.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Terminator {
+    Goto(NodeIndex),
+    CondBr {
+        on_true: NodeIndex,
+        on_false: NodeIndex,
+    },
+    /// Leaves the function. Only `Exit` ends this way.
     Exit,
 }
 
+impl Terminator {
+    /// The terminator with the edges `edges`, if they make up one.
+    fn from_edges(
+        edges: impl IntoIterator<Item = (EdgeCondition, NodeIndex)>,
+    ) -> Option<Terminator> {
+        let (mut goto, mut on_true, mut on_false) = (vec![], vec![], vec![]);
+        for (tag, target) in edges {
+            match tag {
+                EdgeCondition::Unconditional => goto.push(target),
+                EdgeCondition::IfTrue => on_true.push(target),
+                EdgeCondition::IfFalse => on_false.push(target),
+            }
+        }
+        match (&goto[..], &on_true[..], &on_false[..]) {
+            ([], [], []) => Some(Terminator::Exit),
+            (&[target], [], []) => Some(Terminator::Goto(target)),
+            ([], &[on_true], &[on_false]) => Some(Terminator::CondBr { on_true, on_false }),
+            _ => None,
+        }
+    }
+
+    fn edges(self) -> Vec<(EdgeCondition, NodeIndex)> {
+        match self {
+            Terminator::Goto(target) => vec![(EdgeCondition::Unconditional, target)],
+            Terminator::CondBr { on_true, on_false } => vec![
+                (EdgeCondition::IfTrue, on_true),
+                (EdgeCondition::IfFalse, on_false),
+            ],
+            Terminator::Exit => vec![],
+        }
+    }
+}
+
+/// A way in which a CFG is malformed, found by `Cfg::verify`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VerifyError {
+    /// The CFG doesn't have exactly one `Entry` node. Holds how many it has.
+    Entries(usize),
+    /// The CFG doesn't have exactly one `Exit` node. Holds how many it has.
+    Exits(usize),
+    /// The node can't be reached from `Entry`.
+    Unreachable(NodeIndex),
+    /// The node's terminator doesn't suit its kind, eg. a `CondBr` without both a true and a false
+    /// successor, or a statement that was never given a successor.
+    Terminator(NodeIndex),
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VerifyError::Entries(n) => write!(f, "expected 1 entry node, found {}", n),
+            VerifyError::Exits(n) => write!(f, "expected 1 exit node, found {}", n),
+            VerifyError::Unreachable(n) => write!(f, "node {} can't be reached", n.index()),
+            VerifyError::Terminator(n) => {
+                write!(f, "node {} has the wrong kind of successors", n.index())
+            }
+        }
+    }
+}
+
+impl<'ast> Cfg<'ast> {
+    pub fn new() -> Cfg<'ast> {
+        Cfg::default()
+    }
+
+    /// The underlying graph, for reading and for petgraph's algorithms. Its edges are those of each
+    /// node's terminator.
+    pub fn graph(&self) -> &DiGraph<CFGNode<'ast>, EdgeCondition> {
+        &self.graph
+    }
+
+    /// Adds a node with no successors. Every node but `Exit` needs to be given a terminator.
+    pub fn add_node(&mut self, node: CFGNode<'ast>) -> NodeIndex {
+        self.graph.add_node(node)
+    }
+
+    /// Replaces `n`'s successors with those of `terminator`.
+    pub fn set_terminator(&mut self, n: NodeIndex, terminator: Terminator) {
+        while let Some(e) = self.graph.first_edge(n, Direction::Outgoing) {
+            self.graph.remove_edge(e);
+        }
+        for (tag, target) in terminator.edges() {
+            self.graph.add_edge(n, target, tag);
+        }
+    }
+
+    pub fn terminator(&self, n: NodeIndex) -> Terminator {
+        Terminator::from_edges(self.graph.edges(n).map(|e| (*e.weight(), e.target())))
+            .expect("edges are only added by `set_terminator`")
+    }
+
+    /// The `Entry` node, if there is one.
+    pub fn entry(&self) -> Option<NodeIndex> {
+        self.find(|n| matches!(n, CFGNode::Entry))
+    }
+
+    /// The `Exit` node, if there is one.
+    pub fn exit(&self) -> Option<NodeIndex> {
+        self.find(|n| matches!(n, CFGNode::Exit))
+    }
+
+    fn find(&self, pred: impl Fn(&CFGNode) -> bool) -> Option<NodeIndex> {
+        self.graph.node_indices().find(|&n| pred(&self.graph[n]))
+    }
+
+    /// Checks that the CFG has a single `Entry` and a single `Exit`, that every node can be reached
+    /// from `Entry`, and that every node's terminator suits its kind.
+    pub fn verify(&self) -> Result<(), VerifyError> {
+        let count = |pred: fn(&CFGNode) -> bool| {
+            self.graph
+                .node_indices()
+                .filter(|&n| pred(&self.graph[n]))
+                .count()
+        };
+        let entries = count(|n| matches!(n, CFGNode::Entry));
+        if entries != 1 {
+            return Err(VerifyError::Entries(entries));
+        }
+        let exits = count(|n| matches!(n, CFGNode::Exit));
+        if exits != 1 {
+            return Err(VerifyError::Exits(exits));
+        }
+        for n in self.graph.node_indices() {
+            let suits = matches!(
+                (&self.graph[n], self.terminator(n)),
+                (CFGNode::Entry, Terminator::Goto(_))
+                    | (CFGNode::Statement(_), Terminator::Goto(_))
+                    | (CFGNode::CondBr(_), Terminator::CondBr { .. })
+                    | (CFGNode::Exit, Terminator::Exit)
+            );
+            if !suits {
+                return Err(VerifyError::Terminator(n));
+            }
+        }
+        let mut dfs = Dfs::new(&self.graph, self.entry().unwrap());
+        let mut reached = 0;
+        while dfs.next(&self.graph).is_some() {
+            reached += 1;
+        }
+        if reached != self.graph.node_count() {
+            let unreached = self
+                .graph
+                .node_indices()
+                .find(|&n| !dfs.discovered.contains(n.index()));
+            return Err(VerifyError::Unreachable(unreached.unwrap()));
+        }
+        Ok(())
+    }
+}
+
+impl<'ast> Index<NodeIndex> for Cfg<'ast> {
+    type Output = CFGNode<'ast>;
+
+    fn index(&self, n: NodeIndex) -> &CFGNode<'ast> {
+        &self.graph[n]
+    }
+}
+
 /// Builds separate CFGs for each function.
-pub struct IntraprocCFGBuilder {
-    cfg: Vec<Cfg>,
+///
+/// Straight-line statements become one node each. An `if` becomes a `CondBr` on its condition whose
+/// true and false edges lead into its branches, which rejoin at the statement after it. A `while`
+/// becomes a `CondBr` loop header: its true edge leads into the body, the end of the body loops back
+/// to the header, and its false edge and any `break`s leave the loop. `return` and `error` get a
+/// node each, with an edge to `Exit`. Statements that can't be reached, eg. after a `break`, are
+/// left out.
+///
+/// In debug builds, each CFG is checked with `Cfg::verify` once it's built.
+pub struct IntraprocCFGBuilder<'ast> {
+    cfg: Vec<Cfg<'ast>>,
     current_function_idx: usize,
-    last_node_idx: Option<NodeIndex>,
+    /// Edges out of the code lowered so far, which lead to the next node added. Empty when the
+    /// next statement can't be reached.
+    pending: Vec<(NodeIndex, EdgeCondition)>,
+    /// Edges to the current function's `Exit` from its `return` and `error` statements.
+    exits: Vec<(NodeIndex, EdgeCondition)>,
+    /// Edges from the `break`s of each enclosing `while` loop, innermost last.
+    breaks: Vec<Vec<(NodeIndex, EdgeCondition)>>,
+    /// The successor of each branch whose other successor hasn't been added yet.
+    branches: HashMap<NodeIndex, NodeIndex>,
 }
 
-impl IntraprocCFGBuilder {
-    pub fn to_owned_cfg_vec(self) -> Vec<Cfg> {
+impl<'ast> IntraprocCFGBuilder<'ast> {
+    pub fn to_owned_cfg_vec(self) -> Vec<Cfg<'ast>> {
         self.cfg
     }
-    pub fn from_program(p: Program) -> IntraprocCFGBuilder {
+    pub fn from_program(p: &'ast Program) -> IntraprocCFGBuilder<'ast> {
         let mut builder = Self {
             cfg: Vec::with_capacity(p.functions.len()),
             current_function_idx: 0,
-            last_node_idx: None,
+            pending: vec![],
+            exits: vec![],
+            breaks: vec![],
+            branches: HashMap::new(),
         };
         builder.visit_program(p);
         builder
     }
-    fn current_cfg_mut(&mut self) -> &mut Cfg {
-        &mut self.cfg[self.current_function_idx]
+
+    /// Adds a node, connecting the pending edges to it.
+    fn add_node(&mut self, n: CFGNode<'ast>) -> NodeIndex {
+        let this_node = self.cfg[self.current_function_idx].add_node(n);
+        for (from, tag) in mem::take(&mut self.pending) {
+            self.connect(from, tag, this_node);
+        }
+        this_node
     }
 
-    /// Adds a node connected to the last added node.
-    fn append_node(&mut self, n: CFGNode, tag: EdgeCondition) {
-        let last_node = self.last_node_idx;
-        debug_assert!(
-            self.last_node_idx.is_some() || matches!(n, CFGNode::Entry),
-            "last node should only ever be None if the Entry node hasn't been added yet"
-        );
-        let this_node = self.current_cfg_mut().add_node(n);
-        if last_node.is_some() {
-            self.current_cfg_mut().add_edge(
-                last_node.expect("Missing last node -- should at least be the entry node"),
-                this_node,
-                tag,
-            );
+    /// Gives `from` the successor `to`. A branch's terminator is set once both its successors are
+    /// known.
+    fn connect(&mut self, from: NodeIndex, tag: EdgeCondition, to: NodeIndex) {
+        let terminator = match tag {
+            EdgeCondition::Unconditional => Terminator::Goto(to),
+            EdgeCondition::IfTrue | EdgeCondition::IfFalse => match self.branches.remove(&from) {
+                None => {
+                    self.branches.insert(from, to);
+                    return;
+                }
+                Some(other) if tag == EdgeCondition::IfTrue => Terminator::CondBr {
+                    on_true: to,
+                    on_false: other,
+                },
+                Some(other) => Terminator::CondBr {
+                    on_true: other,
+                    on_false: to,
+                },
+            },
+        };
+        self.cfg[self.current_function_idx].set_terminator(from, terminator);
+    }
+
+    fn visit_body(&mut self, body: &'ast Option<StatementList>) {
+        for s in body.iter().flatten() {
+            self.visit_statement(s);
         }
-        self.last_node_idx = Some(this_node);
     }
 }
 
-impl ASTVisitor for IntraprocCFGBuilder {
-    fn visit_function(&mut self, f: Function) {
-        // Insert a new CFG with the entry node in it.
-        self.cfg.push({
-            let mut new_cfg = Cfg::new();
-            self.last_node_idx = Some(new_cfg.add_node(CFGNode::Entry));
-            new_cfg
-        });
-        super_visit_function(self, f);
-        // Cap it off with the exit node.
-        self.append_node(CFGNode::Exit, EdgeCondition::Unconditional);
+impl<'ast> Visitor<'ast> for IntraprocCFGBuilder<'ast> {
+    fn visit_function(&mut self, f: &'ast Function) {
+        self.cfg.push(Cfg::new());
+        let entry = self.add_node(CFGNode::Entry);
+        self.pending.push((entry, EdgeCondition::Unconditional));
+        for s in &f.body {
+            self.visit_statement(s);
+        }
+        // Cap it off with the exit node, which both falling off the end and returning lead to.
+        self.pending.append(&mut self.exits);
+        self.add_node(CFGNode::Exit);
+        debug_assert_eq!(
+            self.cfg[self.current_function_idx].verify(),
+            Ok(()),
+            "invalid CFG for `{}`",
+            f.name.name
+        );
         // Then, update the current function index.
         self.current_function_idx += 1;
     }
 
-    fn visit_statement(&mut self, s: Statement) {
-        self.append_node(CFGNode::Statement(s), EdgeCondition::Unconditional)
+    fn visit_statement(&mut self, s: &'ast Statement) {
+        // Nothing leads here, so the statement can't be reached. That includes any loop in it, since
+        // the loop's back edge comes from its own body, so the whole statement is left out.
+        if self.pending.is_empty() {
+            return;
+        }
+        match &s.kind {
+            StatementKind::If {
+                cond,
+                then,
+                otherwise,
+            } => {
+                let branch = self.add_node(CFGNode::CondBr(cond));
+                self.pending.push((branch, EdgeCondition::IfTrue));
+                self.visit_body(then);
+                let after_then = mem::take(&mut self.pending);
+                self.pending.push((branch, EdgeCondition::IfFalse));
+                self.visit_body(otherwise);
+                self.pending.extend(after_then);
+            }
+            StatementKind::While { cond, then } => {
+                let header = self.add_node(CFGNode::CondBr(cond));
+                self.pending.push((header, EdgeCondition::IfTrue));
+                self.breaks.push(vec![]);
+                self.visit_body(then);
+                for (from, tag) in mem::take(&mut self.pending) {
+                    self.connect(from, tag, header);
+                }
+                self.pending.push((header, EdgeCondition::IfFalse));
+                self.pending.extend(self.breaks.pop().unwrap());
+            }
+            // A `break` outside of a loop is rejected by validation, so it's left as an ordinary
+            // statement if we're asked to build a CFG for it anyway.
+            StatementKind::Break if !self.breaks.is_empty() => {
+                let pending = mem::take(&mut self.pending);
+                self.breaks.last_mut().unwrap().extend(pending);
+            }
+            StatementKind::Return(_) | StatementKind::Error(_) => {
+                let node = self.add_node(CFGNode::Statement(s));
+                self.exits.push((node, EdgeCondition::Unconditional));
+            }
+            StatementKind::Block(body) => {
+                for s in body {
+                    self.visit_statement(s);
+                }
+            }
+            _ => {
+                let node = self.add_node(CFGNode::Statement(s));
+                self.pending.push((node, EdgeCondition::Unconditional));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tip_parser;
+    use petgraph::visit::EdgeRef;
+
+    /// The edges of `cfg`, as `from -> to` with the condition of conditional edges in the arrow,
+    /// sorted.
+    pub(super) fn describe(cfg: &Cfg) -> Vec<String> {
+        let mut edges: Vec<_> = cfg
+            .graph()
+            .edge_references()
+            .map(|e| {
+                let arrow = match e.weight() {
+                    EdgeCondition::Unconditional => "->",
+                    EdgeCondition::IfTrue => "-true->",
+                    EdgeCondition::IfFalse => "-false->",
+                };
+                format!("{} {} {}", cfg[e.source()], arrow, cfg[e.target()])
+            })
+            .collect();
+        edges.sort();
+        edges
+    }
+
+    /// The edges of the CFG of each function in `src`, as given by `describe`.
+    fn edges(src: &str) -> Vec<Vec<String>> {
+        let program = tip_parser::parse(src.to_string()).unwrap();
+        IntraprocCFGBuilder::from_program(&program)
+            .to_owned_cfg_vec()
+            .iter()
+            .map(describe)
+            .collect()
+    }
+
+    pub(super) fn example(name: &str) -> String {
+        std::fs::read_to_string(format!("examples/{}.tip", name)).unwrap()
+    }
+
+    #[test]
+    fn test_loop() {
+        assert_eq!(
+            edges(&example("loop")),
+            [[
+                "[b > i] -false-> return 0;",
+                "[b > i] -true-> i = i + 1;",
+                "a = 5; -> b = 42;",
+                "b = 42; -> i = a;",
+                "entry -> var a, b, i;",
+                "i = a; -> [b > i]",
+                "i = i + 1; -> [b > i]",
+                "return 0; -> exit",
+                "var a, b, i; -> a = 5;",
+            ]]
+        );
+    }
+
+    #[test]
+    fn test_while_short_if() {
+        assert_eq!(
+            edges(&example("while_short_if")),
+            [[
+                "[x == 1] -false-> return y;",
+                "[x == 1] -true-> [x > 0]",
+                "[x > 0] -false-> return y;",
+                "[x > 0] -true-> [y == 0]",
+                "[y == 0] -false-> x = input;",
+                "[y == 0] -true-> output z;",
+                "entry -> var x, y, z;",
+                "output z; -> [x > 0]",
+                "return y; -> exit",
+                "var x, y, z; -> x = input;",
+                "x = input; -> [x > 0]",
+                "x = input; -> y = input;",
+                "y = input; -> z = input;",
+                "z = input; -> [x == 1]",
+            ]]
+        );
+    }
+
+    #[test]
+    fn test_if_short_if() {
+        assert_eq!(
+            edges(&example("if_short_if")),
+            [[
+                "[x == 0] -false-> return y;",
+                "[x == 0] -true-> [y == 0]",
+                "[y == 0] -false-> output x;",
+                "[y == 0] -true-> output z;",
+                "entry -> var x, y, z;",
+                "output x; -> return y;",
+                "output z; -> return y;",
+                "return y; -> exit",
+                "var x, y, z; -> x = input;",
+                "x = input; -> y = input;",
+                "y = input; -> z = input;",
+                "z = input; -> [x == 0]",
+            ]]
+        );
+    }
+
+    #[test]
+    fn test_break_and_return() {
+        assert_eq!(
+            edges("f(x) { while (x > 0) { if (x == 1) { break; } x = x - 1; } { error x; } return x; }"),
+            // `return x;` can't be reached, since `error` ends the program, so it's left out.
+            [[
+                "[x == 1] -false-> x = x - 1;",
+                "[x == 1] -true-> error x;",
+                "[x > 0] -false-> error x;",
+                "[x > 0] -true-> [x == 1]",
+                "entry -> [x > 0]",
+                "error x; -> exit",
+                "x = x - 1; -> [x > 0]",
+            ]]
+        );
+    }
+
+    #[test]
+    fn test_verify() {
+        let program =
+            tip_parser::parse("f(x) { if (x) { x = 1; } return x; }".to_string()).unwrap();
+        let body = &program.functions[0].body;
+        let (cond, assign) = match &body[0].kind {
+            StatementKind::If {
+                cond,
+                then: Some(then),
+                ..
+            } => (cond, &then[0]),
+            s => panic!("expected an if, got {:?}", s),
+        };
+        let mut cfg = Cfg::new();
+        let entry = cfg.add_node(CFGNode::Entry);
+        let branch = cfg.add_node(CFGNode::CondBr(cond));
+        let assign = cfg.add_node(CFGNode::Statement(assign));
+        let ret = cfg.add_node(CFGNode::Statement(&body[1]));
+        let exit = cfg.add_node(CFGNode::Exit);
+        cfg.set_terminator(entry, Terminator::Goto(branch));
+        cfg.set_terminator(assign, Terminator::Goto(ret));
+        cfg.set_terminator(ret, Terminator::Goto(exit));
+        assert_eq!(cfg.verify(), Err(VerifyError::Terminator(branch)));
+        cfg.set_terminator(branch, Terminator::Goto(assign));
+        assert_eq!(cfg.verify(), Err(VerifyError::Terminator(branch)));
+
+        let terminator = Terminator::CondBr {
+            on_true: assign,
+            on_false: ret,
+        };
+        cfg.set_terminator(branch, terminator);
+        assert_eq!(cfg.verify(), Ok(()));
+        assert_eq!(cfg.terminator(branch), terminator);
+        assert_eq!(cfg.graph().edge_count(), 5);
+
+        cfg.set_terminator(entry, Terminator::Goto(ret));
+        assert_eq!(cfg.verify(), Err(VerifyError::Unreachable(branch)));
+        cfg.set_terminator(entry, Terminator::Goto(branch));
+        cfg.add_node(CFGNode::Exit);
+        assert_eq!(cfg.verify(), Err(VerifyError::Exits(2)));
     }
 }
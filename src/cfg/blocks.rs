@@ -0,0 +1,240 @@
+//! Basic blocks: a coarser CFG with a node per run of straight-line statements.
+//!
+//! The statement graph built by `IntraprocCFGBuilder` has a node per statement, which is simple to
+//! analyse but big. A `BlockCfg` groups statements that always run one after another into a single
+//! `BasicBlock`, so analyses that don't need to look between them have fewer nodes to visit. The
+//! two representations convert back and forth.
+
+use super::{CFGNode, Cfg, EdgeCondition, Terminator};
+use crate::ast::{Expression, Statement, StatementKind};
+use petgraph::graph::{DiGraph, NodeIndex};
+use petgraph::visit::EdgeRef;
+use petgraph::Direction;
+use std::collections::HashMap;
+
+/// Statements that always run in order, followed by how the block ends.
+#[derive(Debug)]
+pub struct BasicBlock<'ast> {
+    pub statements: Vec<&'ast Statement>,
+    pub end: BlockEnd<'ast>,
+}
+
+/// How a basic block ends. The edges out of a block must agree with its end.
+#[derive(Debug)]
+pub enum BlockEnd<'ast> {
+    /// Continues to the block's only successor, along an `Unconditional` edge.
+    Jump,
+    /// Branches on a condition, along an `IfTrue` and an `IfFalse` edge.
+    CondBr(&'ast Expression),
+    /// A `return` or `error` statement, with an `Unconditional` edge to the exit block.
+    Return(&'ast Statement),
+    /// The end of the function. Only the exit block ends this way, and it has no statements or
+    /// successors.
+    Exit,
+}
+
+/// A function's CFG, with a node per basic block.
+#[derive(Debug)]
+pub struct BlockCfg<'ast> {
+    pub graph: DiGraph<BasicBlock<'ast>, EdgeCondition>,
+    /// The block the function starts in, which may have no statements.
+    pub entry: NodeIndex,
+}
+
+/// Whether `s` leaves the function, and so ends its block with a `BlockEnd::Return`.
+fn returns(s: &Statement) -> bool {
+    matches!(s.kind, StatementKind::Return(_) | StatementKind::Error(_))
+}
+
+impl<'ast> BlockCfg<'ast> {
+    /// Groups the nodes of a statement graph built by `IntraprocCFGBuilder` into basic blocks.
+    ///
+    /// A block starts at `Entry`, at `Exit`, and at any node that isn't the only successor of a
+    /// node with no other successors. It ends at a `CondBr`, a `return` or `error`, `Exit`, or just
+    /// before the start of another block.
+    pub fn from_cfg(cfg: &Cfg<'ast>) -> BlockCfg<'ast> {
+        let falls_through = |n: NodeIndex| {
+            let straight = match cfg[n] {
+                CFGNode::Entry => true,
+                CFGNode::Statement(s) => !returns(s),
+                CFGNode::CondBr(_) | CFGNode::Exit => false,
+            };
+            straight && cfg.graph().neighbors(n).count() == 1
+        };
+        let starts_block = |n: NodeIndex| match cfg[n] {
+            CFGNode::Entry | CFGNode::Exit => true,
+            _ => {
+                let mut preds = cfg.graph().neighbors_directed(n, Direction::Incoming);
+                match (preds.next(), preds.next()) {
+                    (Some(pred), None) => !falls_through(pred),
+                    _ => true,
+                }
+            }
+        };
+
+        let mut graph = DiGraph::new();
+        let mut block_of = HashMap::new();
+        let mut ends = vec![];
+        let mut entry = None;
+        for start in cfg.graph().node_indices().filter(|&n| starts_block(n)) {
+            let mut statements = vec![];
+            let mut n = start;
+            let end = loop {
+                match cfg[n] {
+                    CFGNode::Entry => {}
+                    CFGNode::Statement(s) if returns(s) => break BlockEnd::Return(s),
+                    CFGNode::Statement(s) => statements.push(s),
+                    CFGNode::CondBr(e) => break BlockEnd::CondBr(e),
+                    CFGNode::Exit => break BlockEnd::Exit,
+                }
+                let next = cfg
+                    .graph()
+                    .neighbors(n)
+                    .next()
+                    .expect("only `Exit` has no successors");
+                if starts_block(next) {
+                    break BlockEnd::Jump;
+                }
+                n = next;
+            };
+            let block = graph.add_node(BasicBlock { statements, end });
+            if let CFGNode::Entry = cfg[start] {
+                entry = Some(block);
+            }
+            block_of.insert(start, block);
+            ends.push((block, n));
+        }
+        for (block, end) in ends {
+            for e in cfg.graph().edges(end) {
+                graph.add_edge(block, block_of[&e.target()], *e.weight());
+            }
+        }
+        BlockCfg {
+            graph,
+            entry: entry.expect("a CFG has an `Entry` node"),
+        }
+    }
+
+    /// The statement graph with the same control flow, with a node per statement. In debug
+    /// builds, it's checked with `Cfg::verify`.
+    ///
+    /// Panics if a block other than the entry block has no statements and ends in a `Jump`, since
+    /// there's no node to stand for it.
+    pub fn to_cfg(&self) -> Cfg<'ast> {
+        let mut cfg = Cfg::new();
+        let mut bounds = HashMap::new();
+        for block in self.graph.node_indices() {
+            let BasicBlock { statements, end } = &self.graph[block];
+            let mut nodes = vec![];
+            if block == self.entry {
+                nodes.push(cfg.add_node(CFGNode::Entry));
+            }
+            nodes.extend(
+                statements
+                    .iter()
+                    .map(|&s| cfg.add_node(CFGNode::Statement(s))),
+            );
+            match *end {
+                BlockEnd::Jump => {}
+                BlockEnd::CondBr(e) => nodes.push(cfg.add_node(CFGNode::CondBr(e))),
+                BlockEnd::Return(s) => nodes.push(cfg.add_node(CFGNode::Statement(s))),
+                BlockEnd::Exit => nodes.push(cfg.add_node(CFGNode::Exit)),
+            }
+            for pair in nodes.windows(2) {
+                cfg.set_terminator(pair[0], Terminator::Goto(pair[1]));
+            }
+            match (nodes.first(), nodes.last()) {
+                (Some(&first), Some(&last)) => bounds.insert(block, (first, last)),
+                _ => panic!("empty basic block that isn't the entry block"),
+            };
+        }
+        for block in self.graph.node_indices() {
+            let edges = self
+                .graph
+                .edges(block)
+                .map(|e| (*e.weight(), bounds[&e.target()].0));
+            let terminator =
+                Terminator::from_edges(edges).expect("a block's edges must agree with its end");
+            cfg.set_terminator(bounds[&block].1, terminator);
+        }
+        debug_assert_eq!(cfg.verify(), Ok(()));
+        cfg
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cfg::tests::{describe, example};
+    use crate::cfg::IntraprocCFGBuilder;
+    use crate::tip_parser;
+
+    /// The edges of `blocks`, like `cfg::tests::describe` but with each block written as its
+    /// statements and how it ends.
+    fn describe_blocks(blocks: &BlockCfg) -> Vec<String> {
+        let label = |b: NodeIndex| {
+            let block = &blocks.graph[b];
+            let mut parts: Vec<_> = block.statements.iter().map(|s| s.to_string()).collect();
+            match block.end {
+                BlockEnd::Jump => {}
+                BlockEnd::CondBr(e) => parts.push(format!("[{}]", e)),
+                BlockEnd::Return(s) => parts.push(s.to_string()),
+                BlockEnd::Exit => parts.push("exit".to_string()),
+            }
+            if b == blocks.entry {
+                parts.insert(0, "entry".to_string());
+            }
+            parts.join(" ")
+        };
+        let mut edges: Vec<_> = blocks
+            .graph
+            .edge_references()
+            .map(|e| {
+                let arrow = match e.weight() {
+                    EdgeCondition::Unconditional => "->",
+                    EdgeCondition::IfTrue => "-true->",
+                    EdgeCondition::IfFalse => "-false->",
+                };
+                format!("{} {} {}", label(e.source()), arrow, label(e.target()))
+            })
+            .collect();
+        edges.sort();
+        edges
+    }
+
+    #[test]
+    fn test_loop() {
+        let program = tip_parser::parse(example("loop")).unwrap();
+        let cfgs = IntraprocCFGBuilder::from_program(&program).to_owned_cfg_vec();
+        let blocks = BlockCfg::from_cfg(&cfgs[0]);
+        assert_eq!(blocks.graph.node_count(), 5);
+        assert_eq!(
+            describe_blocks(&blocks),
+            [
+                "[b > i] -false-> return 0;",
+                "[b > i] -true-> i = i + 1;",
+                "entry var a, b, i; a = 5; b = 42; i = a; -> [b > i]",
+                "i = i + 1; -> [b > i]",
+                "return 0; -> exit",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for entry in std::fs::read_dir("examples").unwrap() {
+            let path = entry.unwrap().path();
+            let program = tip_parser::parse(std::fs::read_to_string(&path).unwrap()).unwrap();
+            for cfg in IntraprocCFGBuilder::from_program(&program).to_owned_cfg_vec() {
+                let blocks = BlockCfg::from_cfg(&cfg);
+                assert!(blocks.graph.node_count() <= cfg.graph().node_count());
+                assert_eq!(
+                    describe(&blocks.to_cfg()),
+                    describe(&cfg),
+                    "converting {} to basic blocks and back",
+                    path.display()
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,345 @@
+//! Dominance: which nodes of a function's CFG every path to (or from) a node must go through.
+//!
+//! A node `a` dominates `b` if every path from `Entry` to `b` goes through `a`, and post-dominates
+//! `b` if every path from `b` to `Exit` goes through `a`. Both relations form trees, rooted at
+//! `Entry` and `Exit`, in which a node's parent is its immediate (post-)dominator. From those come
+//! dominance frontiers, where a node's dominance ends, and control dependence, which says which
+//! branches decide whether a node runs.
+
+use super::{Cfg, EdgeCondition};
+use petgraph::algo::dominators;
+use petgraph::dot::{Config, Dot};
+use petgraph::graph::{DiGraph, NodeIndex};
+use petgraph::visit::{EdgeRef, Reversed};
+use petgraph::Direction;
+use std::collections::{BTreeSet, HashMap};
+
+/// The dominator or post-dominator tree of a CFG.
+#[derive(Debug)]
+pub struct DominatorTree {
+    root: NodeIndex,
+    idom: HashMap<NodeIndex, NodeIndex>,
+    /// The direction of the CFG's edges that paths from the root follow: `Outgoing` for
+    /// dominators, `Incoming` for post-dominators.
+    direction: Direction,
+}
+
+/// Builds the dominator tree of `cfg`, rooted at `Entry`.
+pub fn dominators(cfg: &Cfg) -> DominatorTree {
+    let root = cfg.entry().expect("a CFG has an `Entry` node");
+    let doms = dominators::simple_fast(cfg.graph(), root);
+    DominatorTree::new(cfg, root, Direction::Outgoing, |n| {
+        doms.immediate_dominator(n)
+    })
+}
+
+/// Builds the post-dominator tree of `cfg`, rooted at `Exit`. Nodes that can't reach `Exit`, like
+/// those in a loop that never ends, aren't in it.
+pub fn post_dominators(cfg: &Cfg) -> DominatorTree {
+    let root = cfg.exit().expect("a CFG has an `Exit` node");
+    let doms = dominators::simple_fast(Reversed(cfg.graph()), root);
+    DominatorTree::new(cfg, root, Direction::Incoming, |n| {
+        doms.immediate_dominator(n)
+    })
+}
+
+impl DominatorTree {
+    fn new(
+        cfg: &Cfg,
+        root: NodeIndex,
+        direction: Direction,
+        immediate_dominator: impl Fn(NodeIndex) -> Option<NodeIndex>,
+    ) -> DominatorTree {
+        let idom = cfg
+            .graph()
+            .node_indices()
+            .filter_map(|n| Some((n, immediate_dominator(n)?)))
+            .collect();
+        DominatorTree {
+            root,
+            idom,
+            direction,
+        }
+    }
+
+    pub fn root(&self) -> NodeIndex {
+        self.root
+    }
+
+    /// Whether `n` is in the tree: it's the root, or it can be reached from the root.
+    pub fn contains(&self, n: NodeIndex) -> bool {
+        n == self.root || self.idom.contains_key(&n)
+    }
+
+    /// `n`'s parent in the tree, or `None` for the root and nodes that aren't in the tree.
+    pub fn immediate_dominator(&self, n: NodeIndex) -> Option<NodeIndex> {
+        self.idom.get(&n).copied()
+    }
+
+    /// `n` and its ancestors in the tree, starting with `n`. Empty if `n` isn't in the tree.
+    pub fn dominators(&self, n: NodeIndex) -> Vec<NodeIndex> {
+        if !self.contains(n) {
+            return vec![];
+        }
+        let mut dominators = vec![n];
+        let mut n = n;
+        while let Some(idom) = self.immediate_dominator(n) {
+            dominators.push(idom);
+            n = idom;
+        }
+        dominators
+    }
+
+    /// Whether `a` dominates `b`. Every node in the tree dominates itself.
+    pub fn dominates(&self, a: NodeIndex, b: NodeIndex) -> bool {
+        self.dominators(b).contains(&a)
+    }
+
+    /// The nodes `n` immediately dominates, in the order they're in the CFG.
+    pub fn children(&self, n: NodeIndex) -> Vec<NodeIndex> {
+        let mut children: Vec<_> = self
+            .idom
+            .iter()
+            .filter(|&(_, &idom)| idom == n)
+            .map(|(&child, _)| child)
+            .collect();
+        children.sort();
+        children
+    }
+
+    /// The dominance frontier of each node in the tree: the nodes just beyond where it dominates,
+    /// which it doesn't strictly dominate but one of whose predecessors it does. For a
+    /// post-dominator tree, predecessors are successors in the CFG.
+    pub fn frontiers(&self, cfg: &Cfg) -> HashMap<NodeIndex, BTreeSet<NodeIndex>> {
+        let mut frontiers: HashMap<_, BTreeSet<_>> = HashMap::new();
+        for n in cfg.graph().node_indices().filter(|&n| self.contains(n)) {
+            let preds: Vec<_> = cfg
+                .graph()
+                .neighbors_directed(n, self.direction.opposite())
+                .filter(|&p| self.contains(p))
+                .collect();
+            if preds.len() < 2 {
+                continue;
+            }
+            let idom = self.immediate_dominator(n);
+            for mut runner in preds {
+                while Some(runner) != idom {
+                    frontiers.entry(runner).or_default().insert(n);
+                    match self.immediate_dominator(runner) {
+                        Some(parent) => runner = parent,
+                        None => break,
+                    }
+                }
+            }
+        }
+        frontiers
+    }
+
+    /// The tree in .dot format, with each node labelled as in the CFG.
+    pub fn to_dot(&self, cfg: &Cfg) -> String {
+        let mut tree = DiGraph::<String, &str>::new();
+        let nodes: HashMap<_, _> = cfg
+            .graph()
+            .node_indices()
+            .filter(|&n| self.contains(n))
+            .map(|n| (n, tree.add_node(cfg[n].to_string())))
+            .collect();
+        for n in cfg.graph().node_indices() {
+            if let Some(idom) = self.immediate_dominator(n) {
+                tree.add_edge(nodes[&idom], nodes[&n], "");
+            }
+        }
+        format!("{}", Dot::with_config(&tree, &[Config::EdgeNoLabel]))
+    }
+}
+
+/// A node whose running is decided by a branch: it runs if the branch goes one way, and might not
+/// if it goes the other.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ControlDependence {
+    pub branch: NodeIndex,
+    /// Which way the branch goes for the node to run.
+    pub condition: EdgeCondition,
+    pub dependent: NodeIndex,
+}
+
+/// The control dependences of `cfg`, given its post-dominator tree.
+///
+/// For each edge from `a` to `b` where `b` doesn't post-dominate `a`, the nodes on the path up the
+/// post-dominator tree from `b` to `a`'s immediate post-dominator, not including that, depend on
+/// `a` taking that edge. A loop header depends on itself.
+pub fn control_dependences(cfg: &Cfg, post_dominators: &DominatorTree) -> Vec<ControlDependence> {
+    let mut dependences = vec![];
+    for e in cfg.graph().edge_references() {
+        let (a, b) = (e.source(), e.target());
+        if !post_dominators.contains(a) || post_dominators.dominates(b, a) {
+            continue;
+        }
+        let stop = post_dominators.immediate_dominator(a);
+        for n in post_dominators.dominators(b) {
+            if Some(n) == stop {
+                break;
+            }
+            dependences.push(ControlDependence {
+                branch: a,
+                condition: *e.weight(),
+                dependent: n,
+            });
+        }
+    }
+    dependences.sort_by_key(|d| (d.branch, d.dependent));
+    dependences
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cfg::tests::example;
+    use crate::cfg::{CFGNode, IntraprocCFGBuilder};
+    use crate::tip_parser;
+
+    /// Runs `f` on the CFG of the first function in `src`.
+    fn with_cfg<T>(src: &str, f: impl FnOnce(&Cfg) -> T) -> T {
+        let program = tip_parser::parse(src.to_string()).unwrap();
+        let cfgs = IntraprocCFGBuilder::from_program(&program).to_owned_cfg_vec();
+        f(&cfgs[0])
+    }
+
+    /// The edges of `tree`, as `parent -> child`, sorted.
+    fn tree_edges(cfg: &Cfg, tree: &DominatorTree) -> Vec<String> {
+        let mut edges: Vec<_> = cfg
+            .graph()
+            .node_indices()
+            .filter_map(|n| {
+                Some(format!(
+                    "{} -> {}",
+                    cfg[tree.immediate_dominator(n)?],
+                    cfg[n]
+                ))
+            })
+            .collect();
+        edges.sort();
+        edges
+    }
+
+    #[test]
+    fn test_loop() {
+        with_cfg(&example("loop"), |cfg| {
+            assert_eq!(
+                tree_edges(cfg, &dominators(cfg)),
+                [
+                    "[b > i] -> i = i + 1;",
+                    "[b > i] -> return 0;",
+                    "a = 5; -> b = 42;",
+                    "b = 42; -> i = a;",
+                    "entry -> var a, b, i;",
+                    "i = a; -> [b > i]",
+                    "return 0; -> exit",
+                    "var a, b, i; -> a = 5;",
+                ]
+            );
+            assert_eq!(
+                tree_edges(cfg, &post_dominators(cfg)),
+                [
+                    "[b > i] -> i = a;",
+                    "[b > i] -> i = i + 1;",
+                    "a = 5; -> var a, b, i;",
+                    "b = 42; -> a = 5;",
+                    "exit -> return 0;",
+                    "i = a; -> b = 42;",
+                    "return 0; -> [b > i]",
+                    "var a, b, i; -> entry",
+                ]
+            );
+        });
+    }
+
+    #[test]
+    fn test_frontiers_and_control_dependence() {
+        with_cfg(&example("while_short_if"), |cfg| {
+            let doms = dominators(cfg);
+            let mut frontiers: Vec<_> = doms
+                .frontiers(cfg)
+                .into_iter()
+                .map(|(n, frontier)| {
+                    let frontier: Vec<_> = frontier.iter().map(|&f| cfg[f].to_string()).collect();
+                    format!("{}: {}", cfg[n], frontier.join(", "))
+                })
+                .collect();
+            frontiers.sort();
+            // The loop header is in its own frontier, since the loop's back edges come from nodes it
+            // dominates.
+            assert_eq!(
+                frontiers,
+                [
+                    "[x > 0]: [x > 0], return y;",
+                    "[y == 0]: [x > 0]",
+                    "output z;: [x > 0]",
+                    "x = input;: [x > 0]",
+                ]
+            );
+
+            let post_doms = post_dominators(cfg);
+            let dependences: Vec<_> = control_dependences(cfg, &post_doms)
+                .iter()
+                .map(|d| {
+                    assert!(matches!(cfg[d.branch], CFGNode::CondBr(_)));
+                    format!("{} {:?} {}", cfg[d.branch], d.condition, cfg[d.dependent])
+                })
+                .collect();
+            assert_eq!(
+                dependences,
+                [
+                    "[x == 1] IfTrue [x > 0]",
+                    "[x > 0] IfTrue [x > 0]",
+                    "[x > 0] IfTrue [y == 0]",
+                    "[y == 0] IfTrue output z;",
+                    "[y == 0] IfFalse x = input;",
+                ]
+            );
+        });
+    }
+
+    #[test]
+    fn test_queries_and_dot() {
+        with_cfg("f(x) { if (x) { x = 1; } return x; }", |cfg| {
+            let doms = dominators(cfg);
+            let entry = cfg.entry().unwrap();
+            let exit = cfg.exit().unwrap();
+            let find = |label: &str| {
+                cfg.graph()
+                    .node_indices()
+                    .find(|&n| cfg[n].to_string() == label)
+                    .unwrap()
+            };
+            let (branch, assign, ret) = (find("[x]"), find("x = 1;"), find("return x;"));
+            assert_eq!(doms.root(), entry);
+            assert_eq!(doms.immediate_dominator(ret), Some(branch));
+            assert_eq!(doms.dominators(assign), [assign, branch, entry]);
+            assert!(doms.dominates(branch, exit));
+            assert!(!doms.dominates(assign, ret));
+            assert_eq!(doms.children(branch), [assign, ret]);
+
+            let post_doms = post_dominators(cfg);
+            assert_eq!(post_doms.root(), exit);
+            assert_eq!(post_doms.immediate_dominator(branch), Some(ret));
+            assert!(post_doms.dominates(ret, assign));
+            assert_eq!(
+                post_doms.to_dot(cfg),
+                "\
+digraph {
+    0 [ label = \"entry\" ]
+    1 [ label = \"[x]\" ]
+    2 [ label = \"x = 1;\" ]
+    3 [ label = \"return x;\" ]
+    4 [ label = \"exit\" ]
+    1 -> 0 [ ]
+    3 -> 1 [ ]
+    3 -> 2 [ ]
+    4 -> 3 [ ]
+}
+"
+            );
+        });
+    }
+}
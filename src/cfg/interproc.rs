@@ -0,0 +1,309 @@
+//! A single CFG for the whole program, in which calls lead into the functions they call.
+//!
+//! Each function's nodes come from its intraprocedural CFG, except that a statement or branch
+//! condition containing direct calls is split: each call gets a `Call` node, which passes the
+//! arguments to the callee's parameters and leads to the callee's `Entry`, and an `AfterCall` node,
+//! which the callee's `Exit` leads back to and which assigns the callee's return value to the call.
+//! The original node follows the last of them and uses the calls' values. Calls through function
+//! pointers aren't split, since the callee isn't known until the program runs, and neither are
+//! calls with the wrong number of arguments, which have no parameters to pass them to. The arity
+//! check in `validate` reports those.
+
+use super::{CFGNode, EdgeCondition, IntraprocCFGBuilder};
+use crate::ast::visit::{self, Visitor};
+use crate::ast::{Expression, ExpressionKind, Function, Ident, NodeId, Program};
+use crate::resolve::{DeclKind, Resolution};
+use petgraph::graph::{DiGraph, NodeIndex};
+use petgraph::visit::EdgeRef;
+use std::collections::HashMap;
+
+pub type InterprocGraph<'ast> = DiGraph<InterprocNode<'ast>, InterprocEdge>;
+
+/// A node of the interprocedural CFG.
+#[derive(Debug)]
+pub enum InterprocNode<'ast> {
+    /// A node of a function's own CFG.
+    Intraproc(CFGNode<'ast>),
+    /// Evaluates the arguments of `call`, a direct call of `callee`, and assigns each to the
+    /// callee's parameter it's passed to.
+    Call {
+        call: &'ast Expression,
+        callee: &'ast Function,
+        /// Each of the callee's parameters, with the argument passed to it.
+        params: Vec<(&'ast Ident, &'ast Expression)>,
+    },
+    /// Where `call` returns to. Assigns the callee's return value as the value of `call`.
+    AfterCall { call: &'ast Expression },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InterprocEdge {
+    /// An edge within a function.
+    Intraproc(EdgeCondition),
+    /// From a `Call` node to the callee's `Entry`.
+    Call,
+    /// From a function's `Exit` to the `AfterCall` node of a call to it.
+    Return,
+}
+
+/// Where a function's nodes start and end in the interprocedural CFG.
+#[derive(Debug, Clone, Copy)]
+pub struct FunctionNodes<'ast> {
+    pub function: &'ast Function,
+    pub entry: NodeIndex,
+    pub exit: NodeIndex,
+}
+
+#[derive(Debug)]
+pub struct InterprocCfg<'ast> {
+    pub graph: InterprocGraph<'ast>,
+    /// Each function's `Entry` and `Exit`, in the order they're defined.
+    pub functions: Vec<FunctionNodes<'ast>>,
+    /// The `AfterCall` node of each `Call` node.
+    pub after_call: HashMap<NodeIndex, NodeIndex>,
+}
+
+/// Builds an `InterprocCfg` for a program, which must have been resolved.
+pub struct InterprocCFGBuilder<'ast> {
+    cfg: InterprocCfg<'ast>,
+}
+
+impl<'ast> InterprocCFGBuilder<'ast> {
+    pub fn to_owned_cfg(self) -> InterprocCfg<'ast> {
+        self.cfg
+    }
+
+    pub fn from_program(p: &'ast Program, resolution: &Resolution) -> InterprocCFGBuilder<'ast> {
+        // The index of each function, keyed by its name's id.
+        let functions: HashMap<NodeId, usize> = p
+            .functions
+            .iter()
+            .enumerate()
+            .map(|(idx, f)| (f.name.id, idx))
+            .collect();
+        let mut graph = InterprocGraph::new();
+        let mut nodes = vec![];
+        let mut calls = vec![];
+        let intraproc = IntraprocCFGBuilder::from_program(p).to_owned_cfg_vec();
+        for (f, cfg) in p.functions.iter().zip(&intraproc) {
+            // The first and last node each node of `cfg` is split into.
+            let mut bounds = HashMap::new();
+            for n in cfg.graph().node_indices() {
+                let mut direct_calls = DirectCalls {
+                    program: p,
+                    resolution,
+                    functions: &functions,
+                    calls: vec![],
+                };
+                match cfg[n] {
+                    CFGNode::Statement(s) => direct_calls.visit_statement(s),
+                    CFGNode::CondBr(e) => direct_calls.visit_expression(e),
+                    CFGNode::Entry | CFGNode::Exit => {}
+                }
+                let mut first = None;
+                let mut last = None;
+                for (call, callee_idx, args) in direct_calls.calls {
+                    let callee = &p.functions[callee_idx];
+                    let call_node = graph.add_node(InterprocNode::Call {
+                        call,
+                        callee,
+                        params: callee.params.iter().zip(args).collect(),
+                    });
+                    let after_call = graph.add_node(InterprocNode::AfterCall { call });
+                    if let Some(last) = last {
+                        graph.add_edge(
+                            last,
+                            call_node,
+                            InterprocEdge::Intraproc(EdgeCondition::Unconditional),
+                        );
+                    }
+                    first.get_or_insert(call_node);
+                    last = Some(after_call);
+                    calls.push((call_node, after_call, callee_idx));
+                }
+                let node = graph.add_node(InterprocNode::Intraproc(cfg[n]));
+                if let Some(last) = last {
+                    graph.add_edge(
+                        last,
+                        node,
+                        InterprocEdge::Intraproc(EdgeCondition::Unconditional),
+                    );
+                }
+                bounds.insert(n, (first.unwrap_or(node), node));
+            }
+            for e in cfg.graph().edge_references() {
+                graph.add_edge(
+                    bounds[&e.source()].1,
+                    bounds[&e.target()].0,
+                    InterprocEdge::Intraproc(*e.weight()),
+                );
+            }
+            nodes.push(FunctionNodes {
+                function: f,
+                entry: bounds[&cfg.entry().unwrap()].0,
+                exit: bounds[&cfg.exit().unwrap()].1,
+            });
+        }
+
+        let mut after_call = HashMap::new();
+        for (call_node, after_call_node, callee_idx) in calls {
+            let callee = nodes[callee_idx];
+            graph.add_edge(call_node, callee.entry, InterprocEdge::Call);
+            graph.add_edge(callee.exit, after_call_node, InterprocEdge::Return);
+            after_call.insert(call_node, after_call_node);
+        }
+        InterprocCFGBuilder {
+            cfg: InterprocCfg {
+                graph,
+                functions: nodes,
+                after_call,
+            },
+        }
+    }
+}
+
+/// Collects the direct calls in a statement or expression in the order they're made, so a call's
+/// arguments come before it. Calls with the wrong number of arguments are left out.
+struct DirectCalls<'ast, 'a> {
+    program: &'ast Program,
+    resolution: &'a Resolution,
+    functions: &'a HashMap<NodeId, usize>,
+    /// Each call, with the index of the function it calls and its arguments.
+    calls: Vec<(&'ast Expression, usize, Vec<&'ast Expression>)>,
+}
+
+impl<'ast> Visitor<'ast> for DirectCalls<'ast, '_> {
+    fn visit_expression(&mut self, e: &'ast Expression) {
+        visit::walk_expression(self, e);
+        if let ExpressionKind::Call(callee, args) = &e.kind {
+            if let ExpressionKind::IdentReference(id) = &callee.kind {
+                let function = self
+                    .resolution
+                    .binding(id)
+                    .filter(|b| b.kind == DeclKind::Function);
+                if let Some(function) = function {
+                    let idx = self.functions[&function.decl];
+                    if self.program.functions[idx].params.len() == args.len() {
+                        let args = args.iter().map(|a| &**a).collect();
+                        self.calls.push((e, idx, args));
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cfg::tests::example;
+    use crate::resolve;
+    use crate::tip_parser;
+
+    /// The edges of the interprocedural CFG of `src`, as `from -> to` with the kind of edge in the
+    /// arrow, sorted.
+    fn edges(src: &str) -> Vec<String> {
+        let program = tip_parser::parse(src.to_string()).unwrap();
+        let (resolution, diagnostics) = resolve::resolve(&program);
+        assert!(diagnostics.is_empty());
+        let icfg = InterprocCFGBuilder::from_program(&program, &resolution).to_owned_cfg();
+        let label = |n: NodeIndex| match &icfg.graph[n] {
+            InterprocNode::Intraproc(CFGNode::Entry) | InterprocNode::Intraproc(CFGNode::Exit) => {
+                let f = icfg
+                    .functions
+                    .iter()
+                    .find(|f| f.entry == n || f.exit == n)
+                    .unwrap();
+                let which = if f.entry == n { "entry" } else { "exit" };
+                format!("{} {}", which, f.function.name.name)
+            }
+            InterprocNode::Intraproc(n) => n.to_string(),
+            InterprocNode::Call { call, params, .. } => {
+                let params: Vec<_> = params
+                    .iter()
+                    .map(|(p, a)| format!("{} = {}", p.name, a))
+                    .collect();
+                format!("call {} ({})", call, params.join(", "))
+            }
+            InterprocNode::AfterCall { call } => format!("after {}", call),
+        };
+        let mut edges: Vec<_> = icfg
+            .graph
+            .edge_references()
+            .map(|e| {
+                let arrow = match e.weight() {
+                    InterprocEdge::Intraproc(EdgeCondition::Unconditional) => "->",
+                    InterprocEdge::Intraproc(EdgeCondition::IfTrue) => "-true->",
+                    InterprocEdge::Intraproc(EdgeCondition::IfFalse) => "-false->",
+                    InterprocEdge::Call => "-call->",
+                    InterprocEdge::Return => "-return->",
+                };
+                format!("{} {} {}", label(e.source()), arrow, label(e.target()))
+            })
+            .collect();
+        edges.sort();
+        edges
+    }
+
+    #[test]
+    fn test_calls() {
+        assert_eq!(
+            edges(
+                "inc(n) { return n + 1; } \
+                 main(f) { var x; x = inc(inc(1)); if (inc(x) > f(x)) { x = 0; } return x; }"
+            ),
+            // `f(x)` is a call through a function pointer, so it isn't split.
+            [
+                "[inc(x) > f(x)] -false-> return x;",
+                "[inc(x) > f(x)] -true-> x = 0;",
+                "after inc(1) -> call inc(inc(1)) (n = inc(1))",
+                "after inc(inc(1)) -> x = inc(inc(1));",
+                "after inc(x) -> [inc(x) > f(x)]",
+                "call inc(1) (n = 1) -call-> entry inc",
+                "call inc(inc(1)) (n = inc(1)) -call-> entry inc",
+                "call inc(x) (n = x) -call-> entry inc",
+                "entry inc -> return n + 1;",
+                "entry main -> var x;",
+                "exit inc -return-> after inc(1)",
+                "exit inc -return-> after inc(inc(1))",
+                "exit inc -return-> after inc(x)",
+                "return n + 1; -> exit inc",
+                "return x; -> exit main",
+                "var x; -> call inc(1) (n = 1)",
+                "x = 0; -> return x;",
+                "x = inc(inc(1)); -> call inc(x) (n = x)",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_wrong_number_of_arguments() {
+        // Neither call can pass its arguments to `inc`'s parameters, so they're left as they are.
+        let edges = edges("inc(n) { return n + 1; } main() { return inc() + inc(1, 2); }");
+        assert_eq!(
+            edges,
+            [
+                "entry inc -> return n + 1;",
+                "entry main -> return inc() + inc(1, 2);",
+                "return inc() + inc(1, 2); -> exit main",
+                "return n + 1; -> exit inc",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_signs_fun() {
+        let edges = edges(&example("signs_fun"));
+        assert_eq!(edges.iter().filter(|e| e.contains("-call->")).count(), 6);
+        assert_eq!(edges.iter().filter(|e| e.contains("-return->")).count(), 6);
+        for edge in &[
+            "after idf(5) -> top = idf(5);",
+            "call idf(5) (a = 5) -call-> entry idf",
+            "exit idf -return-> after idf(5)",
+            "exit idf -return-> after idf(-4)",
+            "zero = 0; -> call idf(5) (a = 5)",
+        ] {
+            assert!(edges.contains(&edge.to_string()), "missing {}", edge);
+        }
+    }
+}
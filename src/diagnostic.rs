@@ -0,0 +1,283 @@
+use crate::source_map::{SourceFile, SourceMap, Span};
+use std::fmt::{self, Write};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Note,
+    Warning,
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Severity::Note => "note",
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        })
+    }
+}
+
+/// A span of source code, with a message explaining its part in a `Diagnostic`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Label {
+    pub span: Span,
+    pub message: String,
+}
+
+/// A message for the user about their program, pointing at the code responsible for it.
+///
+/// The primary label marks where the problem is; secondary labels mark other code that explains
+/// why it's a problem (eg. a previous declaration). Notes are printed after the source snippets.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub primary: Option<Label>,
+    pub secondary: Vec<Label>,
+    pub notes: Vec<String>,
+}
+
+impl Diagnostic {
+    pub fn new(severity: Severity, message: impl Into<String>) -> Diagnostic {
+        Diagnostic {
+            severity,
+            message: message.into(),
+            primary: None,
+            secondary: vec![],
+            notes: vec![],
+        }
+    }
+
+    pub fn error(message: impl Into<String>) -> Diagnostic {
+        Self::new(Severity::Error, message)
+    }
+
+    pub fn warning(message: impl Into<String>) -> Diagnostic {
+        Self::new(Severity::Warning, message)
+    }
+
+    pub fn with_primary(mut self, span: Span, message: impl Into<String>) -> Diagnostic {
+        self.primary = Some(Label {
+            span,
+            message: message.into(),
+        });
+        self
+    }
+
+    pub fn with_secondary(mut self, span: Span, message: impl Into<String>) -> Diagnostic {
+        self.secondary.push(Label {
+            span,
+            message: message.into(),
+        });
+        self
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Diagnostic {
+        self.notes.push(note.into());
+        self
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+
+    /// Renders the diagnostic for a terminal, quoting the source lines its labels point at:
+    ///
+    /// ```text
+    /// error: expected `;`
+    ///  --> examples/foo.tip:2:13
+    ///   |
+    /// 2 |     return 1
+    ///   |             ^ unexpected `}`
+    /// ```
+    pub fn render(&self, sm: &SourceMap) -> String {
+        let mut out = String::new();
+        self.render_to(sm, &mut out)
+            .expect("writing to a String can't fail");
+        out
+    }
+
+    fn render_to(&self, sm: &SourceMap, out: &mut String) -> fmt::Result {
+        writeln!(out, "{}: {}", self.severity, self.message)?;
+
+        // Resolve every label we can find source for, primary label first.
+        let mut labels = vec![];
+        if let Some(label) = &self.primary {
+            labels.push((label, true));
+        }
+        labels.extend(self.secondary.iter().map(|l| (l, false)));
+        let labels: Vec<_> = labels
+            .into_iter()
+            .filter_map(|(label, primary)| {
+                let file = sm.lookup_file(label.span.lo)?;
+                let line = file.line_col(label.span.lo).line;
+                Some((file, line, label, primary))
+            })
+            .collect();
+
+        let gutter = labels
+            .iter()
+            .map(|(_, line, _, _)| line.to_string().len())
+            .max()
+            .unwrap_or(0);
+        let blank = " ".repeat(gutter);
+
+        // Print one snippet per file the labels point into, starting with the primary label's.
+        let mut files: Vec<&SourceFile> = vec![];
+        for (file, _, _, _) in &labels {
+            if !files.iter().any(|f| std::ptr::eq(*f, *file)) {
+                files.push(file);
+            }
+        }
+        for (file_idx, file) in files.into_iter().enumerate() {
+            let mut in_file: Vec<_> = labels
+                .iter()
+                .filter(|(f, _, _, _)| std::ptr::eq(*f, file))
+                .collect();
+            let (_, _, first, _) = in_file[0];
+            let loc = sm.lookup(first.span.lo).unwrap();
+            writeln!(
+                out,
+                "{}{} {}",
+                blank,
+                if file_idx == 0 { "-->" } else { ":::" },
+                loc
+            )?;
+            writeln!(out, "{} |", blank)?;
+            // Within a file, show lines in order, with the primary label first on its line.
+            in_file.sort_by_key(|(_, line, label, primary)| (*line, !primary, label.span.lo));
+            let mut last_line = None;
+            for (_, line, label, primary) in in_file {
+                if last_line != Some(*line) {
+                    if matches!(last_line, Some(last) if last + 1 < *line) {
+                        writeln!(out, "{} |", blank)?;
+                    }
+                    let text = file.line(*line).unwrap_or("");
+                    writeln!(out, "{:>width$} | {}", line, text, width = gutter)?;
+                    last_line = Some(*line);
+                }
+                let (indent, width) = underline(file, *line, label.span);
+                let marker = if *primary { "^" } else { "-" };
+                write!(out, "{} | {}{}", blank, indent, marker.repeat(width))?;
+                if label.message.is_empty() {
+                    writeln!(out)?;
+                } else {
+                    writeln!(out, " {}", label.message)?;
+                }
+            }
+        }
+
+        if !labels.is_empty() && !self.notes.is_empty() {
+            writeln!(out, "{} |", blank)?;
+        }
+        for note in &self.notes {
+            writeln!(out, "{} = note: {}", blank, note)?;
+        }
+        Ok(())
+    }
+}
+
+/// Works out the whitespace needed to line up an underline with the start of `span` on `line`, and
+/// the number of characters to underline. Spans running past the end of the line are cut short.
+fn underline(file: &SourceFile, line: usize, span: Span) -> (String, usize) {
+    let text = file.line(line).unwrap_or("");
+    let start_col = file.line_col(span.lo).column - 1;
+    // Keep tabs from the source line so the underline stays aligned however they're displayed.
+    let indent = text
+        .chars()
+        .take(start_col)
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect();
+    let end_col = if span.hi <= file.end_pos() && file.line_col(span.hi).line == line {
+        file.line_col(span.hi).column - 1
+    } else {
+        text.chars().count()
+    };
+    (indent, end_col.saturating_sub(start_col).max(1))
+}
+
+impl fmt::Display for Diagnostic {
+    /// A one-line summary without source context, for when no `SourceMap` is at hand.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.severity, self.message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_primary() {
+        let mut sm = SourceMap::new();
+        let src = "main() {\n    return x;\n}\n";
+        let start = sm.add_file("a.tip", src.to_string()).start_pos;
+        let x = start + src.find('x').unwrap();
+        let diag = Diagnostic::error("undeclared identifier `x`")
+            .with_primary(Span::new(x, x + 1), "not found in this scope")
+            .with_note("identifiers must be declared with `var`");
+        assert_eq!(
+            diag.render(&sm),
+            "\
+error: undeclared identifier `x`
+ --> a.tip:2:12
+  |
+2 |     return x;
+  |            ^ not found in this scope
+  |
+  = note: identifiers must be declared with `var`
+"
+        );
+    }
+
+    #[test]
+    fn test_render_secondary() {
+        let mut sm = SourceMap::new();
+        let src = "f() {\n  var a;\n  var a;\n  return 0;\n}";
+        sm.add_file("a.tip", src.to_string());
+        let first = src.find("a;").unwrap();
+        let second = src.rfind("a;").unwrap();
+        let diag = Diagnostic::error("`a` is declared twice")
+            .with_primary(Span::new(second, second + 1), "redeclared here")
+            .with_secondary(Span::new(first, first + 1), "first declared here");
+        assert_eq!(
+            diag.render(&sm),
+            "\
+error: `a` is declared twice
+ --> a.tip:3:7
+  |
+2 |   var a;
+  |       - first declared here
+3 |   var a;
+  |       ^ redeclared here
+"
+        );
+    }
+
+    #[test]
+    fn test_render_across_files() {
+        let mut sm = SourceMap::new();
+        sm.add_file("a.tip", "f() { return 1; }".to_string());
+        let b = sm
+            .add_file("b.tip", "\n\n\n\n\n\n\n\n\n\nf() { return 2; }".to_string())
+            .start_pos;
+        let diag = Diagnostic::error("function `f` is defined in more than one file")
+            .with_primary(Span::new(b + 10, b + 11), "redefined here")
+            .with_secondary(Span::new(0, 1), "first defined here");
+        assert_eq!(
+            diag.render(&sm),
+            "\
+error: function `f` is defined in more than one file
+  --> b.tip:11:1
+   |
+11 | f() { return 2; }
+   | ^ redefined here
+  ::: a.tip:1:1
+   |
+ 1 | f() { return 1; }
+   | - first defined here
+"
+        );
+    }
+}
@@ -1,3 +1,11 @@
 pub mod ast;
 pub mod cfg;
+pub mod diagnostic;
+pub mod pretty;
+pub mod resolve;
+pub mod source_db;
+pub mod source_map;
+pub mod symbol;
 pub mod tip_parser;
+pub mod types;
+pub mod validate;
@@ -1,6 +1,13 @@
 use std::path::PathBuf;
+use std::process;
+use std::str::FromStr;
 use structopt::StructOpt;
-use tip::tip_parser;
+use tip::diagnostic::Diagnostic;
+use tip::pretty;
+use tip::resolve;
+use tip::source_db::SourceDatabase;
+use tip::types;
+use tip::validate;
 use tip::cfg::IntraprocCFGBuilder;
 use petgraph::dot::{Dot, Config};
 
@@ -12,38 +19,184 @@ struct Opt {
     #[structopt(short, long)]
     /// Dump the AST.
     dump_ast: bool,
+    /// How to dump the AST: `debug`, or `json` (needs the `serde` feature).
+    #[structopt(long, default_value = "debug", possible_values = &["debug", "json"])]
+    format: Format,
     /// Dump the CFG in .dot format.
     #[structopt(short = "c", long)]
     dump_cfg: bool,
+    /// Dump the types of every function and variable.
+    #[structopt(short = "t", long)]
+    dump_types: bool,
+    /// Print the source with the type of each declaration in a comment after it.
+    #[structopt(long)]
+    annotate_types: bool,
     #[structopt(long)]
     verbose: bool,
+    /// Check the files as a library of functions, which doesn't need a `main`.
+    #[structopt(long)]
+    lib: bool,
+    /// Give functions polymorphic types, so that each call can use a function at a different type.
+    #[structopt(long)]
+    poly: bool,
+    #[structopt(subcommand)]
+    cmd: Option<Command>,
+}
+
+#[derive(StructOpt, Debug)]
+enum Command {
+    /// Reformat TIP files in place.
+    Fmt {
+        #[structopt(name = "FILE", parse(from_os_str))]
+        files: Vec<PathBuf>,
+        /// Don't write anything; list the files that aren't formatted, and fail if there are any.
+        #[structopt(long)]
+        check: bool,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Debug,
+    Json,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Format, String> {
+        match s {
+            "debug" => Ok(Format::Debug),
+            "json" => Ok(Format::Json),
+            _ => Err(format!("unknown format `{}`", s)),
+        }
+    }
+}
+
+/// Prints `diagnostics`, and if `fatal` is set, exits if any of them are errors.
+fn report(db: &SourceDatabase, diagnostics: &[Diagnostic], fatal: bool) {
+    for d in diagnostics {
+        eprintln!("{}", d.render(db.source_map()));
+    }
+    if fatal && diagnostics.iter().any(Diagnostic::is_error) {
+        process::exit(1);
+    }
+}
+
+/// Formats each file in `files`, returning the exit code.
+fn fmt(files: &[PathBuf], check: bool) -> i32 {
+    let mut db = SourceDatabase::new();
+    let mut status = 0;
+    for path in files {
+        let file = match db.load_file(path) {
+            Ok(file) => file,
+            Err(e) => {
+                eprintln!("error: couldn't read {}: {}", path.display(), e);
+                status = 1;
+                continue;
+            }
+        };
+        match pretty::format_file(file) {
+            Ok(formatted) if formatted == file.src => {}
+            Ok(_) if check => {
+                println!("{} is not formatted", path.display());
+                status = 1;
+            }
+            Ok(formatted) => {
+                if let Err(e) = std::fs::write(path, formatted) {
+                    eprintln!("error: couldn't write {}: {}", path.display(), e);
+                    status = 1;
+                }
+            }
+            Err(errors) => {
+                for e in &errors {
+                    eprintln!("{}", e.render(db.source_map()));
+                }
+                status = 1;
+            }
+        }
+    }
+    status
 }
 
 fn main() {
     let opt = Opt::from_args();
-    let src = opt
-        .files
-        .into_iter()
-        .fold(String::new(), |mut acc, current| {
-            let src = std::fs::read_to_string(current).unwrap();
-            acc.push_str(&src);
-            acc.push('\n');
-            acc
-        });
+    if opt.dump_ast && opt.format == Format::Json && !cfg!(feature = "serde") {
+        eprintln!("error: `--format json` needs tip to be built with the `serde` feature");
+        process::exit(1);
+    }
+    if let Some(Command::Fmt { files, check }) = &opt.cmd {
+        process::exit(fmt(files, *check));
+    }
+    let mut db = SourceDatabase::new();
+    for path in &opt.files {
+        if let Err(e) = db.load_file(path) {
+            eprintln!("error: couldn't read {}: {}", path.display(), e);
+            process::exit(1);
+        }
+    }
     if opt.verbose {
-        println!("Src is");
-        for (idx, line) in src.lines().enumerate() {
-            println!("{}\t| {}", idx+1, line);
+        for file in db.files() {
+            println!("Src of {} is", file.name);
+            for (idx, line) in file.src.lines().enumerate() {
+                println!("{}\t| {}", idx+1, line);
+            }
+        }
+    }
+    let ast = match db.parse_program() {
+        Ok(ast) => ast,
+        Err(errors) => {
+            for e in &errors {
+                eprintln!("{}", e.render(db.source_map()));
+            }
+            process::exit(1);
         }
+    };
+    // Dumping the AST or CFG works on any program that parses, so errors only stop a run that
+    // checks the program or needs its types.
+    let dump_only = (opt.dump_ast || opt.dump_cfg) && !opt.dump_types && !opt.annotate_types;
+    let (resolution, mut diagnostics) = resolve::resolve(&ast);
+    diagnostics.extend(validate::validate(&ast, &resolution));
+    if !opt.lib && !dump_only {
+        diagnostics.extend(validate::check_main(&ast));
     }
-    let ast = tip_parser::parse(src).unwrap();
+    report(&db, &diagnostics, !dump_only);
+    // The AST and CFG dumps don't need types, so only infer them to check or print them.
+    let types = if dump_only {
+        None
+    } else {
+        let mode = if opt.poly {
+            types::Mode::Polymorphic
+        } else {
+            types::Mode::Monomorphic
+        };
+        let (types, diagnostics) = types::infer(&ast, &resolution, mode);
+        report(&db, &diagnostics, true);
+        Some(types)
+    };
     if opt.dump_ast {
-        println!("{:#?}", ast);
+        match opt.format {
+            Format::Debug => println!("{:#?}", ast),
+            #[cfg(feature = "serde")]
+            Format::Json => println!("{}", tip::ast::json::to_string(&ast)),
+            #[cfg(not(feature = "serde"))]
+            Format::Json => unreachable!(),
+        }
+    }
+    if let Some(types) = &types {
+        if opt.dump_types {
+            print!("{}", types::dump(&ast, types));
+        }
+        if opt.annotate_types {
+            for file in db.files() {
+                print!("{}", types::annotate(file, &ast, types));
+            }
+        }
     }
-    let cfgs = IntraprocCFGBuilder::from_program(ast).to_owned_cfg_vec();
+    let cfgs = IntraprocCFGBuilder::from_program(&ast).to_owned_cfg_vec();
     if opt.dump_cfg {
         for cfg in cfgs {
-            println!("{:#?}", Dot::with_config(&cfg, &[Config::EdgeNoLabel]));
+            println!("{:#?}", Dot::with_config(cfg.graph(), &[Config::EdgeNoLabel]));
         }
     }
 }
@@ -0,0 +1,578 @@
+use crate::ast::{
+    BinOp, Body, Comment, Expression, ExpressionKind, Function, Ident, NodeId, Program, Statement,
+    StatementKind, StatementList, Trivia, UnOp,
+};
+use crate::diagnostic::Diagnostic;
+use crate::source_map::SourceFile;
+use crate::tip_parser;
+use std::fmt;
+
+/// How tightly an expression binds, mirroring the precedence levels in the grammar. Higher binds
+/// tighter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Precedence {
+    Comparison,
+    Additive,
+    Multiplicative,
+    Unary,
+    Projection,
+    Dereference,
+    Call,
+    Atom,
+}
+
+fn precedence(e: &Expression) -> Precedence {
+    match &e.kind {
+        ExpressionKind::BinaryExpression(op, _, _) => match op {
+            BinOp::CompareEq | BinOp::CompareGt => Precedence::Comparison,
+            BinOp::Plus | BinOp::Minus => Precedence::Additive,
+            BinOp::Times | BinOp::Divide => Precedence::Multiplicative,
+        },
+        ExpressionKind::UnaryExpression(UnOp::Dereference, _) => Precedence::Dereference,
+        ExpressionKind::UnaryExpression(_, _) | ExpressionKind::Alloc(_) => Precedence::Unary,
+        ExpressionKind::Projection(_, _) => Precedence::Projection,
+        ExpressionKind::Call(_, _) => Precedence::Call,
+        ExpressionKind::Number(_)
+        | ExpressionKind::IdentReference(_)
+        | ExpressionKind::Input
+        | ExpressionKind::Null
+        | ExpressionKind::Record(_) => Precedence::Atom,
+    }
+}
+
+fn binop_str(op: &BinOp) -> &'static str {
+    match op {
+        BinOp::Plus => "+",
+        BinOp::Minus => "-",
+        BinOp::Times => "*",
+        BinOp::Divide => "/",
+        BinOp::CompareEq => "==",
+        BinOp::CompareGt => ">",
+    }
+}
+
+/// Prints an AST back out as TIP source, with one statement per line and only the parentheses
+/// needed to keep the same structure when parsed again.
+pub struct PrettyPrinter<'a> {
+    indent: String,
+    depth: usize,
+    out: String,
+    trivia: Option<&'a Trivia>,
+}
+
+impl Default for PrettyPrinter<'_> {
+    fn default() -> Self {
+        PrettyPrinter::with_indent("    ")
+    }
+}
+
+impl<'a> PrettyPrinter<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Uses `indent` for each level of indentation, eg. `"\t"` or `"  "`.
+    pub fn with_indent(indent: impl Into<String>) -> Self {
+        PrettyPrinter {
+            indent: indent.into(),
+            depth: 0,
+            out: String::new(),
+            trivia: None,
+        }
+    }
+
+    /// Prints the comments in `trivia` alongside the nodes they're attached to.
+    pub fn with_trivia(mut self, trivia: &'a Trivia) -> Self {
+        self.trivia = Some(trivia);
+        self
+    }
+
+    pub fn finish(self) -> String {
+        self.out
+    }
+
+    pub fn print_program(&mut self, p: &Program) {
+        for (idx, f) in p.functions.iter().enumerate() {
+            if idx > 0 {
+                self.out.push('\n');
+            }
+            self.print_function(f);
+        }
+        let end = self.trivia.map_or(&[][..], |t| &t.end);
+        if !end.is_empty() {
+            self.out.push('\n');
+            self.print_comment_lines(end);
+        }
+    }
+
+    pub fn print_function(&mut self, f: &Function) {
+        self.print_comment_lines(self.comments(|t| t.leading.get(&f.id)));
+        self.start_line();
+        self.print_ident(&f.name);
+        self.out.push('(');
+        self.print_ident_list(&f.params);
+        self.out.push_str(") ");
+        self.print_block(&f.body, (f.id, Body::Main));
+        self.print_trailing_comments(self.comments(|t| t.trailing.get(&f.id)));
+        self.out.push('\n');
+    }
+
+    fn comments(&self, get: impl FnOnce(&'a Trivia) -> Option<&'a Vec<Comment>>) -> &'a [Comment] {
+        self.trivia.and_then(get).map_or(&[], Vec::as_slice)
+    }
+
+    /// Prints comments on lines of their own.
+    fn print_comment_lines(&mut self, comments: &[Comment]) {
+        for c in comments {
+            self.start_line();
+            self.print_comment(c);
+            self.out.push('\n');
+        }
+    }
+
+    /// Prints comments at the end of the current line. Line comments run to the end of the line, so
+    /// this has to be the last thing printed before the newline, and any comments after a line
+    /// comment go on lines of their own.
+    fn print_trailing_comments(&mut self, comments: &[Comment]) {
+        let mut after_line_comment = false;
+        for c in comments {
+            if after_line_comment {
+                self.out.push('\n');
+                self.start_line();
+            } else {
+                self.out.push(' ');
+            }
+            self.print_comment(c);
+            after_line_comment = c.text.starts_with("//");
+        }
+    }
+
+    fn print_comment(&mut self, c: &Comment) {
+        // Block comments can span lines, which should end the same way as the lines around them.
+        self.out.push_str(&c.text.trim_end().replace("\r\n", "\n"));
+    }
+
+    fn print_ident(&mut self, id: &Ident) {
+        self.out.push_str(id.name.as_str());
+    }
+
+    fn print_ident_list(&mut self, ids: &[Ident]) {
+        for (idx, id) in ids.iter().enumerate() {
+            if idx > 0 {
+                self.out.push_str(", ");
+            }
+            self.print_ident(id);
+        }
+    }
+
+    fn start_line(&mut self) {
+        for _ in 0..self.depth {
+            self.out.push_str(&self.indent);
+        }
+    }
+
+    /// Prints a braced statement list, leaving the output just after the closing brace. `owner`
+    /// identifies the list's comments in the trivia.
+    fn print_block(&mut self, body: &[Statement], owner: (NodeId, Body)) {
+        self.out.push('{');
+        // Comments that can't share the brace's line belong with the body.
+        self.depth += 1;
+        self.print_trailing_comments(self.comments(|t| t.body_start.get(&owner)));
+        self.out.push('\n');
+        for s in body {
+            if self
+                .trivia
+                .is_some_and(|t| t.blank_line_before.contains(&s.id))
+            {
+                self.out.push('\n');
+            }
+            self.print_statement(s);
+        }
+        self.print_comment_lines(self.comments(|t| t.body_end.get(&owner)));
+        self.depth -= 1;
+        self.start_line();
+        self.out.push('}');
+    }
+
+    fn print_optional_block(&mut self, body: &Option<StatementList>, owner: (NodeId, Body)) {
+        self.print_block(body.as_deref().unwrap_or_default(), owner)
+    }
+
+    /// Prints a statement on its own line(s), including the trailing newline.
+    pub fn print_statement(&mut self, s: &Statement) {
+        self.print_comment_lines(self.comments(|t| t.leading.get(&s.id)));
+        self.start_line();
+        match &s.kind {
+            StatementKind::VarDecl(ids) => {
+                self.out.push_str("var ");
+                self.print_ident_list(ids);
+                self.out.push(';');
+            }
+            StatementKind::Assign(lhs, rhs) => {
+                self.print_expression(lhs);
+                self.out.push_str(" = ");
+                self.print_expression(rhs);
+                self.out.push(';');
+            }
+            StatementKind::If {
+                cond,
+                then,
+                otherwise,
+            } => {
+                self.out.push_str("if (");
+                self.print_expression(cond);
+                self.out.push_str(") ");
+                self.print_optional_block(then, (s.id, Body::Main));
+                if let Some(otherwise) = otherwise {
+                    self.out.push_str(" else ");
+                    self.print_block(otherwise, (s.id, Body::Else));
+                }
+            }
+            StatementKind::While { cond, then } => {
+                self.out.push_str("while (");
+                self.print_expression(cond);
+                self.out.push_str(") ");
+                self.print_optional_block(then, (s.id, Body::Main));
+            }
+            StatementKind::Break => self.out.push_str("break;"),
+            StatementKind::Output(e) => {
+                self.out.push_str("output ");
+                self.print_expression(e);
+                self.out.push(';');
+            }
+            StatementKind::Return(e) => {
+                self.out.push_str("return");
+                if let Some(e) = e {
+                    self.out.push(' ');
+                    self.print_expression(e);
+                }
+                self.out.push(';');
+            }
+            StatementKind::Error(e) => {
+                self.out.push_str("error ");
+                self.print_expression(e);
+                self.out.push(';');
+            }
+            StatementKind::ExpressionStatement(e) => {
+                self.print_expression(e);
+                self.out.push(';');
+            }
+            StatementKind::Block(body) => self.print_block(body, (s.id, Body::Main)),
+            // There's no source to print for code that didn't parse.
+            StatementKind::Invalid => self.out.push_str("/* invalid statement */"),
+        }
+        self.print_trailing_comments(self.comments(|t| t.trailing.get(&s.id)));
+        self.out.push('\n');
+    }
+
+    pub fn print_expression(&mut self, e: &Expression) {
+        match &e.kind {
+            ExpressionKind::Number(n) => self.out.push_str(&n.to_string()),
+            ExpressionKind::IdentReference(id) => self.print_ident(id),
+            ExpressionKind::Input => self.out.push_str("input"),
+            ExpressionKind::Null => self.out.push_str("null"),
+            ExpressionKind::BinaryExpression(op, l, r) => {
+                // Binary operators are left associative, so an operand at the same level only
+                // needs parentheses on the right.
+                let prec = precedence(e);
+                self.print_operand(l, precedence(l) < prec);
+                self.out.push(' ');
+                self.out.push_str(binop_str(op));
+                self.out.push(' ');
+                self.print_operand(r, precedence(r) <= prec);
+            }
+            ExpressionKind::UnaryExpression(op, operand) => {
+                self.out.push_str(match op {
+                    UnOp::Negate => "-",
+                    UnOp::AddressOf => "&",
+                    UnOp::Dereference => "*",
+                });
+                self.print_operand(operand, precedence(operand) < precedence(e));
+            }
+            ExpressionKind::Alloc(operand) => {
+                self.out.push_str("alloc ");
+                self.print_operand(operand, precedence(operand) < precedence(e));
+            }
+            ExpressionKind::Call(f, args) => {
+                self.print_postfix_operand(f, Precedence::Call);
+                self.out.push('(');
+                for (idx, arg) in args.iter().enumerate() {
+                    if idx > 0 {
+                        self.out.push_str(", ");
+                    }
+                    self.print_expression(arg);
+                }
+                self.out.push(')');
+            }
+            ExpressionKind::Record(fields) => {
+                self.out.push('{');
+                for (idx, (id, e)) in fields.iter().enumerate() {
+                    if idx > 0 {
+                        self.out.push_str(", ");
+                    }
+                    self.print_ident(id);
+                    self.out.push_str(": ");
+                    self.print_expression(e);
+                }
+                self.out.push('}');
+            }
+            ExpressionKind::Projection(record, fields) => {
+                // `a.f.g` is a single projection, so a projection of a projection needs
+                // parentheses to keep its shape.
+                if matches!(record.kind, ExpressionKind::Projection(_, _)) {
+                    self.print_operand(record, true);
+                } else {
+                    self.print_postfix_operand(record, Precedence::Projection);
+                }
+                for field in fields {
+                    self.out.push('.');
+                    self.print_ident(field);
+                }
+            }
+        }
+    }
+
+    /// Prints the operand of a postfix operator. Other postfix expressions can be chained without
+    /// parentheses, but a prefix or binary operator only keeps its operand to itself if it binds
+    /// more tightly than the postfix operator.
+    fn print_postfix_operand(&mut self, e: &Expression, prec: Precedence) {
+        let is_postfix = matches!(
+            e.kind,
+            ExpressionKind::Call(_, _) | ExpressionKind::Projection(_, _)
+        );
+        self.print_operand(e, !is_postfix && precedence(e) < prec);
+    }
+
+    fn print_operand(&mut self, e: &Expression, parenthesize: bool) {
+        if parenthesize {
+            self.out.push('(');
+        }
+        self.print_expression(e);
+        if parenthesize {
+            self.out.push(')');
+        }
+    }
+}
+
+/// Formats a file as `tip fmt` does, keeping its comments. If the file doesn't parse, returns its
+/// syntax errors instead.
+pub fn format_file(file: &SourceFile) -> Result<String, Vec<Diagnostic>> {
+    match tip_parser::parse_file_with_trivia(file) {
+        Ok((program, trivia)) => {
+            let mut printer = PrettyPrinter::new().with_trivia(&trivia);
+            printer.print_program(&program);
+            Ok(printer.finish())
+        }
+        Err(_) => Err(tip_parser::parse_file_recovering(file).1),
+    }
+}
+
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut printer = PrettyPrinter::new();
+        printer.print_program(self);
+        f.write_str(&printer.finish())
+    }
+}
+
+impl fmt::Display for Function {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut printer = PrettyPrinter::new();
+        printer.print_function(self);
+        f.write_str(&printer.finish())
+    }
+}
+
+impl fmt::Display for Statement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut printer = PrettyPrinter::new();
+        printer.print_statement(self);
+        f.write_str(printer.finish().trim_end())
+    }
+}
+
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut printer = PrettyPrinter::new();
+        printer.print_expression(self);
+        f.write_str(&printer.finish())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source_map::SourceMap;
+
+    fn reprint_expression(src: &str) -> String {
+        let program = tip_parser::parse(format!("main() {{ return {}; }}", src)).unwrap();
+        match &program.functions[0].body[0].kind {
+            StatementKind::Return(Some(e)) => e.to_string(),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_minimal_parentheses() {
+        let table = [
+            ("(1 + 2) * 3", "(1 + 2) * 3"),
+            ("1 + (2 * 3)", "1 + 2 * 3"),
+            ("(1 - 2) - 3", "1 - 2 - 3"),
+            ("1 - (2 - 3)", "1 - (2 - 3)"),
+            ("(a > b) == (c > d)", "a > b == (c > d)"),
+            ("-(a)", "-a"),
+            ("-(a + b)", "-(a + b)"),
+            ("- (-a)", "--a"),
+            ("(*a).f", "*a.f"),
+            ("*(a.f)", "*(a.f)"),
+            ("(a.f).g", "(a.f).g"),
+            ("a.f.g", "a.f.g"),
+            ("(*f)(x)", "(*f)(x)"),
+            ("*(f(x))", "*f(x)"),
+            ("(f(x)).y", "f(x).y"),
+            ("(a.f)(x)", "a.f(x)"),
+            ("(-a).f", "(-a).f"),
+            ("-(a.f)", "-a.f"),
+            ("*(-a)", "*(-a)"),
+            ("-(*a)", "-*a"),
+            ("alloc (a + 1)", "alloc (a + 1)"),
+            ("(alloc a) + 1", "alloc a + 1"),
+            ("(alloc {f: 1}).f", "(alloc {f: 1}).f"),
+            ("f((a + b), (g(c)))", "f(a + b, g(c))"),
+            ("{a: (1), b: {c: null}}", "{a: 1, b: {c: null}}"),
+            ("&(*x)", "&*x"),
+        ];
+        for (src, expected) in table.iter() {
+            assert_eq!(&reprint_expression(src), expected, "printing `{}`", src);
+        }
+    }
+
+    #[test]
+    fn test_print_program() {
+        let src = "\
+f(x,y){var z;z=x+y;if(z>0){output z;}else{z=0;}while(z>0)z=z-1;return z;}
+main(){return f(1,input);}";
+        let expected = "\
+f(x, y) {
+    var z;
+    z = x + y;
+    if (z > 0) {
+        output z;
+    } else {
+        z = 0;
+    }
+    while (z > 0) {
+        z = z - 1;
+    }
+    return z;
+}
+
+main() {
+    return f(1, input);
+}
+";
+        assert_eq!(
+            tip_parser::parse(src.to_string()).unwrap().to_string(),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_configurable_indent() {
+        let program = tip_parser::parse("main() { if (1) { return 0; } }".to_string()).unwrap();
+        let mut printer = PrettyPrinter::with_indent("\t");
+        printer.print_program(&program);
+        assert_eq!(
+            printer.finish(),
+            "main() {\n\tif (1) {\n\t\treturn 0;\n\t}\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_format_comments() {
+        let src = "\
+// Leads f.
+f(x) /* header */ { // opens f
+  var y; // trails var
+
+    // Leads the if.
+  if (x /* in cond */ > 0) { y = 1; } // trails the if
+  else { // opens else
+    y = x + /* inside */ 2;
+    // ends else
+  }
+  while (y > 0) y = y - 1; // trails while
+  return y;
+  // ends f
+}
+/* after f */
+main() { return f(1); }
+// at the end
+";
+        let expected = "\
+// Leads f.
+f(x) { /* header */ // opens f
+    var y; // trails var
+
+    // Leads the if.
+    if (x > 0) { /* in cond */
+        y = 1; // trails the if
+    } else { // opens else
+        y = x + 2; /* inside */
+        // ends else
+    }
+    while (y > 0) {
+        y = y - 1;
+    } // trails while
+    return y;
+    // ends f
+}
+
+/* after f */
+main() {
+    return f(1);
+}
+
+// at the end
+";
+        let mut sm = SourceMap::new();
+        let file = sm.add_file("test.tip", src.to_string());
+        let formatted = format_file(file).unwrap();
+        assert_eq!(formatted, expected);
+        let file = sm.add_file("formatted.tip", formatted);
+        assert_eq!(format_file(file).unwrap(), expected);
+    }
+    #[test]
+    fn test_format_comment_after_line_comment() {
+        let src = "\
+main() { // opens main
+  /* first */ var x; x = 1 + // c
+      /* d */ 2;
+  if (x // e
+      > /* f */ 0) { x = 0; }
+  return x;
+}
+";
+        // Only the last comment on a line can be a line comment, or the ones after it would become
+        // part of it.
+        let expected = "\
+main() { // opens main
+    /* first */
+    var x;
+    x = 1 + 2; // c
+    /* d */
+    if (x > 0) { // e
+        /* f */
+        x = 0;
+    }
+    return x;
+}
+";
+        let mut sm = SourceMap::new();
+        let file = sm.add_file("test.tip", src.to_string());
+        let formatted = format_file(file).unwrap();
+        assert_eq!(formatted, expected);
+        let file = sm.add_file("formatted.tip", formatted);
+        assert_eq!(format_file(file).unwrap(), expected);
+    }
+}
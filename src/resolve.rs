@@ -0,0 +1,295 @@
+//! Name resolution: binds every identifier to the function, parameter or local variable it names.
+//!
+//! TIP has a single namespace. Functions are visible everywhere, and each function's parameters and
+//! locals are visible throughout its body. A local can shadow a function, which is allowed but
+//! warned about.
+
+use crate::ast::visit::{self, Visitor};
+use crate::ast::{Expression, Function, Ident, NodeId, NodeMap, Program, Statement, StatementKind};
+use crate::diagnostic::Diagnostic;
+use crate::source_map::Span;
+use crate::symbol::Symbol;
+use std::collections::hash_map::{Entry, HashMap};
+use std::collections::HashSet;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeclKind {
+    Function,
+    Param,
+    Local,
+}
+
+/// The declaration an identifier refers to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Binding {
+    pub kind: DeclKind,
+    /// The declaring identifier: a function's name, a parameter or a name in a `var` statement.
+    pub decl: NodeId,
+    pub span: Span,
+}
+
+impl Binding {
+    fn new(kind: DeclKind, decl: &Ident) -> Binding {
+        Binding {
+            kind,
+            decl: decl.id,
+            span: decl.span,
+        }
+    }
+}
+
+/// The result of name resolution: a binding for every identifier that names a function or
+/// variable, whether it's declaring or using it. Record fields aren't bound.
+#[derive(Debug, Default)]
+pub struct Resolution {
+    pub bindings: NodeMap<Binding>,
+}
+
+impl Resolution {
+    /// What `id` refers to, or `None` if it's undeclared.
+    pub fn binding(&self, id: &Ident) -> Option<Binding> {
+        self.bindings.get(&id.id).copied()
+    }
+}
+
+/// Resolves every identifier in `program`, reporting undeclared identifiers (once per function),
+/// names declared more than once and locals shadowing functions.
+pub fn resolve(program: &Program) -> (Resolution, Vec<Diagnostic>) {
+    let mut resolver = Resolver {
+        functions: HashMap::new(),
+        locals: HashMap::new(),
+        undeclared: HashSet::new(),
+        resolution: Resolution::default(),
+        diagnostics: vec![],
+    };
+    for f in &program.functions {
+        match resolver.functions.entry(f.name.name) {
+            Entry::Occupied(e) => resolver.diagnostics.push(
+                Diagnostic::error(format!(
+                    "function `{}` is defined more than once",
+                    f.name.name
+                ))
+                .with_primary(f.name.span, "redefined here")
+                .with_secondary(e.get().span, "first defined here"),
+            ),
+            Entry::Vacant(e) => {
+                e.insert(Binding::new(DeclKind::Function, &f.name));
+            }
+        }
+    }
+    resolver.visit_program(program);
+    (resolver.resolution, resolver.diagnostics)
+}
+
+struct Resolver {
+    functions: HashMap<Symbol, Binding>,
+    /// Parameters and locals of the function being resolved.
+    locals: HashMap<Symbol, Binding>,
+    /// Undeclared names already reported in the function being resolved.
+    undeclared: HashSet<Symbol>,
+    resolution: Resolution,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Resolver {
+    fn bind(&mut self, id: &Ident, binding: Binding) {
+        self.resolution.bindings.insert(id.id, binding);
+    }
+
+    fn declare(&mut self, id: &Ident, kind: DeclKind) {
+        let binding = Binding::new(kind, id);
+        match self.locals.entry(id.name) {
+            Entry::Occupied(e) => {
+                self.diagnostics.push(
+                    Diagnostic::error(format!("`{}` is declared twice", id.name))
+                        .with_primary(id.span, "redeclared here")
+                        .with_secondary(e.get().span, "first declared here"),
+                );
+                return;
+            }
+            Entry::Vacant(e) => {
+                e.insert(binding);
+            }
+        }
+        if let Some(function) = self.functions.get(&id.name) {
+            self.diagnostics.push(
+                Diagnostic::warning(format!("`{}` shadows a function", id.name))
+                    .with_primary(id.span, "declared here")
+                    .with_secondary(function.span, "function defined here")
+                    .with_note(format!(
+                        "the function can't be used by name while `{}` is in scope",
+                        id.name
+                    )),
+            );
+        }
+        self.bind(id, binding);
+    }
+}
+
+/// Collects the names declared by `var` statements, wherever they are in a function.
+#[derive(Default)]
+struct Locals<'ast>(Vec<&'ast Ident>);
+
+impl<'ast> Visitor<'ast> for Locals<'ast> {
+    fn visit_statement(&mut self, s: &'ast Statement) {
+        if let StatementKind::VarDecl(ids) = &s.kind {
+            self.0.extend(ids);
+        }
+        visit::walk_statement(self, s)
+    }
+    fn visit_expression(&mut self, _e: &'ast Expression) {}
+}
+
+impl<'ast> Visitor<'ast> for Resolver {
+    fn visit_function(&mut self, f: &'ast Function) {
+        if let Some(&binding) = self.functions.get(&f.name.name) {
+            // Only the first definition of a function gets to be what its name refers to.
+            if binding.decl == f.name.id {
+                self.bind(&f.name, binding);
+            }
+        }
+        self.locals.clear();
+        self.undeclared.clear();
+        for param in &f.params {
+            self.declare(param, DeclKind::Param);
+        }
+        let mut locals = Locals::default();
+        for s in &f.body {
+            locals.visit_statement(s);
+        }
+        for id in locals.0 {
+            self.declare(id, DeclKind::Local);
+        }
+        for s in &f.body {
+            self.visit_statement(s);
+        }
+    }
+
+    fn visit_statement(&mut self, s: &'ast Statement) {
+        // Declarations were bound along with the parameters.
+        if !matches!(s.kind, StatementKind::VarDecl(_)) {
+            visit::walk_statement(self, s)
+        }
+    }
+
+    fn visit_ident(&mut self, id: &'ast Ident) {
+        match self
+            .locals
+            .get(&id.name)
+            .or_else(|| self.functions.get(&id.name))
+        {
+            Some(&binding) => self.bind(id, binding),
+            None => {
+                if self.undeclared.insert(id.name) {
+                    self.diagnostics.push(
+                        Diagnostic::error(format!("undeclared identifier `{}`", id.name))
+                            .with_primary(id.span, "not found in this scope"),
+                    )
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source_map::SourceMap;
+    use crate::tip_parser;
+
+    /// Resolves `src`, returning the diagnostics as `severity line:col: message` strings.
+    fn check(src: &str) -> Vec<String> {
+        let mut sm = SourceMap::new();
+        let file = sm.add_file("test.tip", src.to_string());
+        let program = tip_parser::parse_file(file).unwrap();
+        let (_, diagnostics) = resolve(&program);
+        diagnostics
+            .iter()
+            .map(|d| {
+                let loc = sm.lookup(d.primary.as_ref().unwrap().span.lo).unwrap();
+                format!("{} {}:{}: {}", d.severity, loc.line, loc.column, d.message)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_bindings() {
+        let src = "f(a) { var b; b = a + f(g); return b; } g() { return 0; }";
+        let program = tip_parser::parse(src.to_string()).unwrap();
+        let (resolution, diagnostics) = resolve(&program);
+        assert!(diagnostics.is_empty());
+
+        struct Check<'a>(&'a Resolution, Vec<(String, DeclKind, usize)>);
+        impl<'ast> Visitor<'ast> for Check<'_> {
+            fn visit_ident(&mut self, id: &'ast Ident) {
+                let binding = self.0.binding(id).unwrap();
+                self.1
+                    .push((id.name.to_string(), binding.kind, binding.span.lo));
+            }
+        }
+        let mut check = Check(&resolution, vec![]);
+        check.visit_program(&program);
+        let pos = |pat: &str| src.find(pat).unwrap();
+        assert_eq!(
+            check.1,
+            [
+                ("f".to_string(), DeclKind::Function, 0),
+                ("a".to_string(), DeclKind::Param, 2),
+                ("b".to_string(), DeclKind::Local, pos("b;")),
+                ("b".to_string(), DeclKind::Local, pos("b;")),
+                ("a".to_string(), DeclKind::Param, 2),
+                ("f".to_string(), DeclKind::Function, 0),
+                ("g".to_string(), DeclKind::Function, pos("g()")),
+                ("b".to_string(), DeclKind::Local, pos("b;")),
+                ("g".to_string(), DeclKind::Function, pos("g()")),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_shadowing_a_function() {
+        assert_eq!(
+            check("f() { return 1; } g(f) { var g; return f; }"),
+            [
+                "warning 1:21: `f` shadows a function",
+                "warning 1:30: `g` shadows a function",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_err_examples() {
+        // The examples that name resolution rejects. The other `err_*` examples are caught by later
+        // passes.
+        let table: &[(&str, &[&str])] = &[
+            ("err_notdecl", &["error 2:5: undeclared identifier `a`"]),
+            (
+                "err_decl_use",
+                &[
+                    "error 2:14: undeclared identifier `a`",
+                    "error 10:5: undeclared identifier `a`",
+                ],
+            ),
+            ("err_doubledecl1", &["error 3:5: `q` is declared twice"]),
+            ("err_doubledecl2", &["error 2:5: `x` is declared twice"]),
+            ("err_notlocal", &["error 8:5: undeclared identifier `n`"]),
+            (
+                "err_locals",
+                &["error 6:1: function `f` is defined more than once"],
+            ),
+        ];
+        for entry in std::fs::read_dir("examples").unwrap() {
+            let path = entry.unwrap().path();
+            let name = path.file_stem().unwrap().to_str().unwrap();
+            if !name.starts_with("err_") {
+                continue;
+            }
+            let expected = table
+                .iter()
+                .find(|(n, _)| *n == name)
+                .map_or(&[][..], |(_, e)| e);
+            let src = std::fs::read_to_string(&path).unwrap();
+            assert_eq!(check(&src), expected, "resolving {}", name);
+        }
+    }
+}
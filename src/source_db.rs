@@ -0,0 +1,150 @@
+use crate::ast::{Function, Program};
+use crate::diagnostic::Diagnostic;
+use crate::source_map::{SourceFile, SourceMap, Span};
+use crate::symbol::Symbol;
+use crate::tip_parser;
+use std::collections::hash_map::{Entry, HashMap};
+use std::path::Path;
+
+/// The set of source files making up a TIP program. Each file is parsed on its own, so that errors
+/// and spans refer to the file they came from, and the results are then merged into one `Program`.
+#[derive(Debug, Default)]
+pub struct SourceDatabase {
+    source_map: SourceMap,
+}
+
+impl SourceDatabase {
+    pub fn new() -> SourceDatabase {
+        Self::default()
+    }
+
+    pub fn add_file(&mut self, name: impl Into<String>, src: String) -> &SourceFile {
+        self.source_map.add_file(name, src)
+    }
+
+    /// Reads a file from disk and adds it to the database.
+    pub fn load_file(&mut self, path: &Path) -> std::io::Result<&SourceFile> {
+        let src = std::fs::read_to_string(path)?;
+        Ok(self.add_file(path.display().to_string(), src))
+    }
+
+    pub fn source_map(&self) -> &SourceMap {
+        &self.source_map
+    }
+
+    pub fn files(&self) -> &[SourceFile] {
+        self.source_map.files()
+    }
+
+    /// The file a function was defined in.
+    pub fn file_of(&self, f: &Function) -> Option<&SourceFile> {
+        self.source_map.lookup_file(f.span.lo)
+    }
+
+    /// Parses every file and merges the results into a single program, with functions in the order
+    /// their files were added.
+    pub fn parse_program(&self) -> Result<Program, Vec<Diagnostic>> {
+        let mut errors = vec![];
+        let mut functions = vec![];
+        // Maps each function name to the index of the file that first defined it. Functions with
+        // the same name in a single file are left for later passes to report; here we only care
+        // about definitions that clash across files.
+        let mut defined_in: HashMap<Symbol, (usize, Span)> = HashMap::new();
+        for (file_idx, file) in self.files().iter().enumerate() {
+            let (program, syntax_errors) = tip_parser::parse_file_recovering(file);
+            errors.extend(syntax_errors);
+            for f in program.functions {
+                match defined_in.entry(f.name.name) {
+                    Entry::Occupied(e) => {
+                        let (first_file_idx, first) = *e.get();
+                        if first_file_idx != file_idx {
+                            errors.push(
+                                Diagnostic::error(format!(
+                                    "function `{}` is defined in more than one file",
+                                    f.name.name
+                                ))
+                                .with_primary(f.name.span, "redefined here")
+                                .with_secondary(first, "first defined here"),
+                            );
+                        }
+                    }
+                    Entry::Vacant(e) => {
+                        e.insert((file_idx, f.name.span));
+                    }
+                }
+                functions.push(f);
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(Program { functions })
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_merge_files() {
+        let mut db = SourceDatabase::new();
+        db.add_file("a.tip", "f() { return 1; }\ng() { return 2; }".to_string());
+        db.add_file("b.tip", "main() { return f() + g(); }".to_string());
+        let program = db.parse_program().unwrap();
+        let names: Vec<_> = program
+            .functions
+            .iter()
+            .map(|f| f.name.name.as_str())
+            .collect();
+        assert_eq!(names, ["f", "g", "main"]);
+        let provenance: Vec<_> = program
+            .functions
+            .iter()
+            .map(|f| db.file_of(f).unwrap().name.as_str())
+            .collect();
+        assert_eq!(provenance, ["a.tip", "a.tip", "b.tip"]);
+    }
+
+    #[test]
+    fn test_parse_error_is_relative_to_file() {
+        let mut db = SourceDatabase::new();
+        db.add_file("a.tip", "f() {\n  return 1;\n}\n".to_string());
+        db.add_file("b.tip", "main() {\n  return 1 +;\n}".to_string());
+        let errors = db.parse_program().unwrap_err();
+        assert_eq!(errors.len(), 1);
+        let span = errors[0].primary.as_ref().unwrap().span;
+        assert_eq!(db.source_map().span_to_string(span), "b.tip:2:13");
+    }
+
+    #[test]
+    fn test_duplicate_function_across_files() {
+        let mut db = SourceDatabase::new();
+        db.add_file("a.tip", "f() { return 1; }".to_string());
+        db.add_file(
+            "b.tip",
+            "main() { return 0; }\nf() { return 2; }".to_string(),
+        );
+        let errors = db.parse_program().unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            errors[0].message,
+            "function `f` is defined in more than one file"
+        );
+        let sm = db.source_map();
+        assert_eq!(
+            sm.span_to_string(errors[0].primary.as_ref().unwrap().span),
+            "b.tip:2:1"
+        );
+        assert_eq!(sm.span_to_string(errors[0].secondary[0].span), "a.tip:1:1");
+    }
+
+    #[test]
+    fn test_duplicate_function_in_one_file_is_not_a_load_error() {
+        let mut db = SourceDatabase::new();
+        db.add_file("a.tip", "f() { return 1; }\nf() { return 2; }".to_string());
+        assert_eq!(db.parse_program().unwrap().functions.len(), 2);
+    }
+}
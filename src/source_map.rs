@@ -0,0 +1,218 @@
+use std::fmt;
+
+/// A half-open range `[lo, hi)` of byte offsets into a `SourceMap`.
+///
+/// Offsets are global to the `SourceMap` rather than relative to a single file, so a span alone is
+/// enough to find the file it came from.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Span {
+    pub lo: usize,
+    pub hi: usize,
+}
+
+impl Span {
+    /// Span for nodes which don't come from any source text, eg. nodes built by hand in tests.
+    pub const DUMMY: Span = Span { lo: 0, hi: 0 };
+
+    pub fn new(lo: usize, hi: usize) -> Span {
+        debug_assert!(lo <= hi, "span ends before it starts");
+        Span { lo, hi }
+    }
+
+    /// The smallest span covering both `self` and `other`.
+    pub fn to(self, other: Span) -> Span {
+        Span::new(self.lo.min(other.lo), self.hi.max(other.hi))
+    }
+
+    pub fn len(self) -> usize {
+        self.hi - self.lo
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.lo == self.hi
+    }
+
+    /// Whether this is `Span::DUMMY`. Takes a reference so it can be used with serde's
+    /// `skip_serializing_if`.
+    pub fn is_dummy(&self) -> bool {
+        *self == Span::DUMMY
+    }
+}
+
+/// A line and column in a file. Both are 1-based.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineCol {
+    pub line: usize,
+    pub column: usize,
+}
+
+/// A single source file that has been loaded into a `SourceMap`.
+#[derive(Debug)]
+pub struct SourceFile {
+    pub name: String,
+    pub src: String,
+    /// Offset of the first byte of this file within its `SourceMap`.
+    pub start_pos: usize,
+    /// Offsets (relative to the start of the file) of the first byte of each line.
+    line_starts: Vec<usize>,
+}
+
+impl SourceFile {
+    fn new(name: String, src: String, start_pos: usize) -> SourceFile {
+        let line_starts = std::iter::once(0)
+            .chain(src.match_indices('\n').map(|(idx, _)| idx + 1))
+            .collect();
+        SourceFile {
+            name,
+            src,
+            start_pos,
+            line_starts,
+        }
+    }
+
+    /// Offset one past the last byte of this file.
+    pub fn end_pos(&self) -> usize {
+        self.start_pos + self.src.len()
+    }
+
+    pub fn contains(&self, pos: usize) -> bool {
+        self.start_pos <= pos && pos <= self.end_pos()
+    }
+
+    /// Converts a position within this file to a line and column.
+    pub fn line_col(&self, pos: usize) -> LineCol {
+        debug_assert!(self.contains(pos), "position isn't in {}", self.name);
+        let offset = pos - self.start_pos;
+        let line_idx = match self.line_starts.binary_search(&offset) {
+            Ok(idx) => idx,
+            Err(idx) => idx - 1,
+        };
+        LineCol {
+            line: line_idx + 1,
+            column: self.src[self.line_starts[line_idx]..offset].chars().count() + 1,
+        }
+    }
+
+    /// The text of the given 1-based line, without its line terminator.
+    pub fn line(&self, line: usize) -> Option<&str> {
+        let start = *self.line_starts.get(line.checked_sub(1)?)?;
+        let end = self
+            .line_starts
+            .get(line)
+            .copied()
+            .unwrap_or(self.src.len());
+        Some(self.src[start..end].trim_end_matches(['\n', '\r']))
+    }
+
+    pub fn line_count(&self) -> usize {
+        self.line_starts.len()
+    }
+
+    /// The source text covered by `span`.
+    pub fn source_text(&self, span: Span) -> &str {
+        &self.src[span.lo - self.start_pos..span.hi - self.start_pos]
+    }
+}
+
+/// A resolved source location, as reported to the user.
+#[derive(Debug, Clone, Copy)]
+pub struct Loc<'a> {
+    pub file: &'a SourceFile,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl fmt::Display for Loc<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.file.name, self.line, self.column)
+    }
+}
+
+/// Owns the source text of every file being compiled, and maps spans back to the file, line and
+/// column they came from.
+#[derive(Debug, Default)]
+pub struct SourceMap {
+    files: Vec<SourceFile>,
+}
+
+impl SourceMap {
+    pub fn new() -> SourceMap {
+        Self::default()
+    }
+
+    /// Adds a file to the map. The file's offsets start after those of every file added before it.
+    pub fn add_file(&mut self, name: impl Into<String>, src: String) -> &SourceFile {
+        // Leave a gap of one byte between files so that a position at the very end of one file
+        // can't be confused with the start of the next.
+        let start_pos = self.files.last().map_or(0, |f| f.end_pos() + 1);
+        self.files
+            .push(SourceFile::new(name.into(), src, start_pos));
+        self.files.last().unwrap()
+    }
+
+    pub fn files(&self) -> &[SourceFile] {
+        &self.files
+    }
+
+    /// The file containing the given position, if any.
+    pub fn lookup_file(&self, pos: usize) -> Option<&SourceFile> {
+        let idx = match self.files.binary_search_by_key(&pos, |f| f.start_pos) {
+            Ok(idx) => idx,
+            Err(0) => return None,
+            Err(idx) => idx - 1,
+        };
+        self.files.get(idx).filter(|f| f.contains(pos))
+    }
+
+    pub fn lookup(&self, pos: usize) -> Option<Loc<'_>> {
+        let file = self.lookup_file(pos)?;
+        let LineCol { line, column } = file.line_col(pos);
+        Some(Loc { file, line, column })
+    }
+
+    /// Formats the start of a span as `file.tip:line:column`.
+    pub fn span_to_string(&self, span: Span) -> String {
+        match self.lookup(span.lo) {
+            Some(loc) => loc.to_string(),
+            None => "<unknown>".to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_line_col() {
+        let mut sm = SourceMap::new();
+        let file = sm.add_file("a.tip", "ab\ncd\n\nef".to_string());
+        assert_eq!(file.line_col(0), LineCol { line: 1, column: 1 });
+        assert_eq!(file.line_col(2), LineCol { line: 1, column: 3 });
+        assert_eq!(file.line_col(3), LineCol { line: 2, column: 1 });
+        assert_eq!(file.line_col(6), LineCol { line: 3, column: 1 });
+        assert_eq!(file.line_col(8), LineCol { line: 4, column: 2 });
+        assert_eq!(file.line(2), Some("cd"));
+        assert_eq!(file.line(3), Some(""));
+        assert_eq!(file.line(4), Some("ef"));
+        assert_eq!(file.line(5), None);
+    }
+
+    #[test]
+    fn test_multiple_files() {
+        let mut sm = SourceMap::new();
+        let a_end = sm.add_file("a.tip", "main() {}\n".to_string()).end_pos();
+        let b_start = sm
+            .add_file("b.tip", "f() {\n  return 1;\n}".to_string())
+            .start_pos;
+        assert!(b_start > a_end);
+        assert_eq!(sm.lookup_file(0).unwrap().name, "a.tip");
+        assert_eq!(sm.lookup_file(b_start).unwrap().name, "b.tip");
+        assert_eq!(
+            sm.span_to_string(Span::new(b_start + 8, b_start + 14)),
+            "b.tip:2:3"
+        );
+        assert!(sm.lookup_file(b_start + 1000).is_none());
+    }
+}
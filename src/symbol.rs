@@ -0,0 +1,151 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{LazyLock, Mutex, OnceLock};
+
+/// An interned string, used for identifiers.
+///
+/// Symbols are small and `Copy`, and two symbols are equal exactly when their strings are, so
+/// comparing and hashing them never looks at the text. The text lives in a global table for the
+/// rest of the program; get it back with `as_str`.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Symbol(u32);
+
+/// Maps strings to symbols. Strings are leaked, so they can be handed out as `&'static str`.
+static SYMBOLS: LazyLock<Mutex<HashMap<&'static str, Symbol>>> = LazyLock::new(Mutex::default);
+
+/// The string of each symbol, by number, so that `as_str` doesn't need the lock on `SYMBOLS`.
+///
+/// It's append-only, in chunks that double in size: chunk `k` holds symbols `2^k - 1` up to
+/// `2^(k + 1) - 2`. Chunks are never moved or freed, and each slot is written once, before its
+/// symbol is handed out.
+static STRINGS: [OnceLock<Box<[OnceLock<&'static str>]>>; 32] = [const { OnceLock::new() }; 32];
+
+/// The chunk of `STRINGS` a symbol's string is in, and its index in that chunk.
+fn slot(sym: Symbol) -> (usize, usize) {
+    let n = u64::from(sym.0) + 1;
+    let chunk = n.ilog2() as usize;
+    (chunk, (n - (1 << chunk)) as usize)
+}
+
+impl Symbol {
+    pub fn intern(s: &str) -> Symbol {
+        let mut symbols = SYMBOLS.lock().unwrap();
+        if let Some(&sym) = symbols.get(s) {
+            return sym;
+        }
+        let sym = Symbol(symbols.len() as u32);
+        let s: &'static str = Box::leak(s.into());
+        let (chunk, idx) = slot(sym);
+        let chunk =
+            STRINGS[chunk].get_or_init(|| (0..1 << chunk).map(|_| OnceLock::new()).collect());
+        chunk[idx].set(s).unwrap();
+        symbols.insert(s, sym);
+        sym
+    }
+
+    /// The text this symbol was interned from.
+    pub fn as_str(self) -> &'static str {
+        let (chunk, idx) = slot(self);
+        STRINGS[chunk]
+            .get()
+            .and_then(|chunk| chunk[idx].get())
+            .expect("symbols are only made by `intern`, which stores their text first")
+    }
+}
+
+impl From<&str> for Symbol {
+    fn from(s: &str) -> Symbol {
+        Symbol::intern(s)
+    }
+}
+
+impl PartialEq<str> for Symbol {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl PartialEq<&str> for Symbol {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+impl fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Symbols are serialised as their text, since their numbers are only meaningful within one run.
+#[cfg(feature = "serde")]
+impl serde::Serialize for Symbol {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Symbol {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Symbol, D::Error> {
+        let s = <std::borrow::Cow<'de, str>>::deserialize(deserializer)?;
+        Ok(Symbol::intern(&s))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_interning() {
+        let a = Symbol::intern("interning_test_a");
+        let b = Symbol::intern("interning_test_b");
+        assert_ne!(a, b);
+        assert_eq!(a, Symbol::intern(&String::from("interning_test_a")));
+        assert_eq!(a.as_str(), "interning_test_a");
+        assert_eq!(b, "interning_test_b");
+        assert_eq!(
+            format!("{} {:?}", a, b),
+            "interning_test_a \"interning_test_b\""
+        );
+    }
+
+    #[test]
+    fn test_slots() {
+        let slots: Vec<_> = (0..7).map(|n| slot(Symbol(n))).collect();
+        assert_eq!(
+            slots,
+            [(0, 0), (1, 0), (1, 1), (2, 0), (2, 1), (2, 2), (2, 3)]
+        );
+        assert_eq!(slot(Symbol(u32::MAX - 1)), (31, (1 << 31) - 1));
+    }
+
+    #[test]
+    fn test_interning_across_threads() {
+        let threads: Vec<_> = (0..4)
+            .map(|t| {
+                std::thread::spawn(move || {
+                    (0..500)
+                        .map(|n| {
+                            let s = format!("thread_test_{}", (n * (t + 1)) % 700);
+                            (Symbol::intern(&s), s)
+                        })
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        for thread in threads {
+            for (sym, s) in thread.join().unwrap() {
+                assert_eq!(sym.as_str(), s);
+                assert_eq!(sym, Symbol::intern(&s));
+            }
+        }
+    }
+}
@@ -1,8 +1,290 @@
 mod grammar;
-use crate::ast::Program;
+mod trivia;
+use crate::ast::{Comment, Program, Statement, StatementKind, Trivia};
+use crate::diagnostic::Diagnostic;
+use crate::source_map::{SourceFile, Span};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
 
-pub type ParseError =  peg::error::ParseError<peg::str::LineCol>;
+pub type ParseError = peg::error::ParseError<peg::str::LineCol>;
+
+/// What the grammar expects in place of a number literal too big for an `i64`. `syntax_error`
+/// reports errors expecting this as an out-of-range literal rather than a list of expected tokens.
+const NUMBER_OUT_OF_RANGE: &str = "a number that fits in 64 bits";
+
+/// State threaded through every rule of the grammar.
+pub(crate) struct ParseContext {
+    /// Offset of the input within its `SourceMap`, added to every position the grammar records.
+    base: usize,
+    /// Whether to skip over syntax errors, leaving `StatementKind::Invalid` nodes in their place.
+    recover: bool,
+    /// Every comment skipped so far, by position. Rules can be tried more than once at the same
+    /// position when the parser backtracks, so this is keyed to keep each comment only once.
+    comments: RefCell<BTreeMap<usize, Comment>>,
+}
+
+impl ParseContext {
+    fn new(base: usize) -> ParseContext {
+        ParseContext {
+            base,
+            recover: false,
+            comments: RefCell::default(),
+        }
+    }
+
+    fn recovering(base: usize) -> ParseContext {
+        ParseContext {
+            base,
+            recover: true,
+            comments: RefCell::default(),
+        }
+    }
+
+    /// Converts input-relative positions into a `SourceMap` span.
+    fn span(&self, lo: usize, hi: usize) -> Span {
+        Span::new(self.base + lo, self.base + hi)
+    }
+
+    fn record_comment(&self, text: &str, lo: usize, hi: usize) {
+        let span = self.span(lo, hi);
+        self.comments
+            .borrow_mut()
+            .entry(span.lo)
+            .or_insert(Comment {
+                text: text.to_string(),
+                span,
+            });
+    }
+
+    /// The comments skipped while parsing, in source order.
+    fn take_comments(&self) -> Vec<Comment> {
+        std::mem::take(&mut *self.comments.borrow_mut())
+            .into_values()
+            .collect()
+    }
+}
 
 pub fn parse(src: String) -> Result<Program, ParseError> {
-    grammar::tip_parser::program(&src)
+    grammar::tip_parser::program(&src, &ParseContext::new(0))
+}
+
+/// Parses a file from a `SourceMap`, so that the spans in the resulting AST point into that file.
+pub fn parse_file(file: &SourceFile) -> Result<Program, ParseError> {
+    grammar::tip_parser::program(&file.src, &ParseContext::new(file.start_pos))
+}
+
+/// Parses a file, keeping its comments as `Trivia` attached to the statements and functions around
+/// them.
+pub fn parse_file_with_trivia(file: &SourceFile) -> Result<(Program, Trivia), ParseError> {
+    let ctx = ParseContext::new(file.start_pos);
+    let program = grammar::tip_parser::program(&file.src, &ctx)?;
+    let trivia = trivia::attach_comments(file, &program, ctx.take_comments());
+    Ok((program, trivia))
+}
+
+/// Parses a file, recovering from syntax errors so that as many as possible can be reported in one
+/// go. Returns whatever could be parsed, with `StatementKind::Invalid` in place of statements that
+/// couldn't be, along with a diagnostic for each error.
+pub fn parse_file_recovering(file: &SourceFile) -> (Program, Vec<Diagnostic>) {
+    let ctx = ParseContext::recovering(file.start_pos);
+    let (program, skipped_functions) =
+        match grammar::tip_parser::program_recovering(&file.src, &ctx) {
+            Ok(result) => result,
+            // Recovery can skip over anything but an input without any functions at all.
+            Err(e) => return (Program { functions: vec![] }, vec![syntax_error(file, &e)]),
+        };
+
+    // Re-parse from each place we recovered, without recovery, to find out what went wrong.
+    let strict = |skipped: Span, function: bool| {
+        let offset = skipped.lo - file.start_pos;
+        let ctx = ParseContext::new(skipped.lo);
+        let src = &file.src[offset..];
+        let result = if function {
+            grammar::tip_parser::function_prefix(src, &ctx)
+        } else {
+            grammar::tip_parser::statement_prefix(src, &ctx)
+        };
+        match result {
+            Err(e) => syntax_error_at(file, offset, &e),
+            // The text parses on its own, so it was skipped because of what came before it. Still
+            // report it, since the program has an `Invalid` node in its place.
+            Ok(()) => {
+                Diagnostic::error("syntax error").with_primary(skipped, "couldn't parse this")
+            }
+        }
+    };
+    let mut invalid = vec![];
+    for f in &program.functions {
+        collect_invalid(&f.body, &mut invalid);
+    }
+    let mut errors: Vec<_> = skipped_functions
+        .into_iter()
+        .map(|s| (s.lo, strict(s, true)))
+        .chain(invalid.into_iter().map(|s| (s.lo, strict(s, false))))
+        .collect();
+    errors.sort_by_key(|(lo, _)| *lo);
+    (program, errors.into_iter().map(|(_, e)| e).collect())
+}
+
+fn collect_invalid(stmts: &[Statement], spans: &mut Vec<Span>) {
+    for s in stmts {
+        match &s.kind {
+            StatementKind::Invalid => spans.push(s.span),
+            StatementKind::If {
+                then, otherwise, ..
+            } => {
+                collect_invalid(then.as_deref().unwrap_or_default(), spans);
+                collect_invalid(otherwise.as_deref().unwrap_or_default(), spans);
+            }
+            StatementKind::While { then, .. } => {
+                collect_invalid(then.as_deref().unwrap_or_default(), spans)
+            }
+            StatementKind::Block(body) => collect_invalid(body, spans),
+            _ => {}
+        }
+    }
+}
+
+/// Converts an error from parsing `file` into a `Diagnostic`.
+pub fn syntax_error(file: &SourceFile, error: &ParseError) -> Diagnostic {
+    syntax_error_at(file, 0, error)
+}
+
+/// Like `syntax_error`, for errors from parsing the part of `file` starting at `start`.
+fn syntax_error_at(file: &SourceFile, start: usize, error: &ParseError) -> Diagnostic {
+    let offset = start + error.location.offset;
+    if error.expected.tokens().any(|t| t == NUMBER_OUT_OF_RANGE) {
+        let len = file.src[offset..]
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(file.src.len() - offset);
+        let pos = file.start_pos + offset;
+        return Diagnostic::error("number literal is too large").with_primary(
+            Span::new(pos, pos + len),
+            format!("must be at most {}", i64::MAX),
+        );
+    }
+    let mut expected: Vec<String> = error
+        .expected
+        .tokens()
+        .map(
+            |t| match t.strip_prefix('"').and_then(|t| t.strip_suffix('"')) {
+                Some(literal) => format!("`{}`", literal),
+                None => t.to_string(),
+            },
+        )
+        .collect();
+    expected.sort();
+    let message = match expected.as_slice() {
+        [] => "syntax error".to_string(),
+        [one] => format!("expected {}", one),
+        many => format!("expected one of {}", many.join(", ")),
+    };
+    let (len, label) = match file.src[offset..].chars().next() {
+        Some(c) => (c.len_utf8(), format!("unexpected `{}`", c.escape_default())),
+        None => (0, "unexpected end of file".to_string()),
+    };
+    let pos = file.start_pos + offset;
+    Diagnostic::error(message).with_primary(Span::new(pos, pos + len), label)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source_map::SourceMap;
+
+    fn parse_recovering(src: &str) -> (Program, Vec<String>) {
+        let mut sm = SourceMap::new();
+        let file = sm.add_file("test.tip", src.to_string());
+        let (program, errors) = parse_file_recovering(file);
+        let errors = errors
+            .iter()
+            .map(|e| {
+                let span = e.primary.as_ref().unwrap().span;
+                format!("{}: {}", sm.span_to_string(span), e.message)
+            })
+            .collect();
+        (program, errors)
+    }
+
+    #[test]
+    fn test_recover_statements() {
+        let (program, errors) = parse_recovering(
+            "\
+main() {
+    var x;
+    x = 1 +;
+    output x;
+    while (x > 0) {
+        x = x - ;
+    }
+    return x
+}",
+        );
+        assert_eq!(
+            errors,
+            [
+                "test.tip:3:12: expected one of `&`, `(`, `*`, `-`, `alloc`, `input`, `null`, `{`, identifier, number",
+                "test.tip:6:17: expected one of `&`, `(`, `*`, `-`, `alloc`, `input`, `null`, `{`, identifier, number",
+                "test.tip:9:1: expected one of `(`, `*`, `+`, `-`, `/`, `;`, `==`, `>`",
+            ]
+        );
+        let body = &program.functions[0].body;
+        assert_eq!(body.len(), 5);
+        assert_eq!(body[1].kind, StatementKind::Invalid);
+        assert_eq!(body[4].kind, StatementKind::Invalid);
+        match &body[3].kind {
+            StatementKind::While {
+                then: Some(then), ..
+            } => assert_eq!(then[0].kind, StatementKind::Invalid),
+            _ => panic!("expected a while loop"),
+        }
+    }
+
+    #[test]
+    fn test_recover_functions() {
+        let (program, errors) = parse_recovering(
+            "\
+f( { return 1; }
+g() { return 2; }
+h(x { if (x) { return 3; } }
+main() { return g(); }",
+        );
+        assert_eq!(
+            errors,
+            [
+                "test.tip:1:4: expected identifier",
+                "test.tip:3:5: expected one of `)`, `,`",
+            ]
+        );
+        let names: Vec<_> = program
+            .functions
+            .iter()
+            .map(|f| f.name.name.as_str())
+            .collect();
+        assert_eq!(names, ["g", "main"]);
+    }
+
+    #[test]
+    fn test_number_out_of_range() {
+        let src = "main() { return 99999999999999999999; }";
+        let mut sm = SourceMap::new();
+        let file = sm.add_file("test.tip", src.to_string());
+        let error = syntax_error(file, &parse_file(file).unwrap_err());
+        let primary = error.primary.as_ref().unwrap();
+        assert_eq!(error.message, "number literal is too large");
+        assert_eq!(
+            &src[primary.span.lo..primary.span.hi],
+            "99999999999999999999"
+        );
+        assert_eq!(
+            parse_recovering(src).1,
+            ["test.tip:1:17: number literal is too large"]
+        );
+        assert!(parse("main() { return 9223372036854775807; }".to_string()).is_ok());
+    }
+
+    #[test]
+    fn test_recovery_is_off_by_default() {
+        assert!(parse("main() { x = 1 +; return 0; }".to_string()).is_err());
+    }
 }
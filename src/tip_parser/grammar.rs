@@ -1,10 +1,23 @@
-use crate::ast::{BinOp, Expression, Function, Ident, Program, Statement, StatementList, UnOp};
+use super::{ParseContext, NUMBER_OUT_OF_RANGE};
+use crate::ast::{
+    BinOp, Expression, ExpressionKind, Function, Ident, Program, Statement, StatementKind,
+    StatementList, UnOp,
+};
+use crate::source_map::Span;
 use peg;
 
+fn binary(op: BinOp, l: Expression, r: Expression) -> Expression {
+    let span = l.span.to(r.span);
+    Expression::new(
+        ExpressionKind::BinaryExpression(op, Box::new(l), Box::new(r)),
+        span,
+    )
+}
+
 peg::parser! {
     /// PEG grammar for TIP. Note that we wrap this parser in the `TipParser` struct. Most rules are `pub`
     /// to make them testable.
-    pub(crate) grammar tip_parser() for str {
+    pub(crate) grammar tip_parser(ctx: &ParseContext) for str {
 
         rule block_comment_content()
             = (!"*/" [_])
@@ -12,316 +25,564 @@ peg::parser! {
         rule line_comment_content()
             = (!"\n" [_])
 
-        // A bit delicate -- perhaps implement a custom iterator instead that skips comments?
-        // Would ruin line numbers though
+        // Comments are skipped like whitespace, but recorded in `ctx` so that tools which print the
+        // program back out can keep them.
         pub rule comment()
-            = "//" line_comment_content()*
-            / "/*" block_comment_content()* "*/"
+            = lo:position!() text:$("//" line_comment_content()* / "/*" block_comment_content()* "*/") hi:position!() {
+                ctx.record_comment(text, lo, hi)
+            }
 
-        rule ws() = [' ' | '\n' | '\t' | '\r' ]+
+        rule ws() = quiet!{[' ' | '\n' | '\t' | '\r' ]+} / expected!("whitespace")
 
         rule blank()
             = ws()
             / comment()
 
         rule _()
-            = blank()*
+            = quiet!{blank()*}
 
         rule statement_list() -> StatementList
-            = stmt:(_ s:statement() _ { s })+ { stmt }
+            = stmt:(_ s:(statement() / invalid_statement()) _ { s })+ { stmt }
+
+        // Error recovery. When `ctx.recover` is set, text that doesn't parse is skipped up to the next
+        // `;`, unmatched `}` or (at the top level) the end of the next function body, and the parser
+        // carries on from there.
+
+        rule recovering()
+            = quiet!{{? if ctx.recover { Ok(()) } else { Err("") } }}
+
+        rule skipped_token()
+            = comment()
+            / "{" skipped_token()* "}"
+            / !['{' | '}'] [_]
+
+        rule invalid_statement() -> Statement
+            = recovering() lo:position!() s:$((!";" skipped_token())+ ";"?) {
+                Statement::new(StatementKind::Invalid, ctx.span(lo, lo + s.trim_end().len()))
+            }
+
+        rule skipped_function() -> Span
+            = lo:position!() s:$((!"{" skipped_token())* "{" skipped_token()* "}" / [_]+) {
+                ctx.span(lo, lo + s.trim_end().len())
+            }
 
-        rule statement_contents() -> Statement
+        rule statement_contents() -> StatementKind
             = "var" ws() first:ident() rest:("," _ id:ident() { id })* {
                 let mut result = vec![first];
                 result.extend(rest);
-                Statement::VarDecl(result)
+                StatementKind::VarDecl(result)
             }
-            / "break" { Statement::Break }
-            / "return" e:(ws() e:expression() { e })?  { Statement::Return(e) }
-            / "output" ws() e:expression() { Statement::Output(e) }
-            / "error" ws() e:expression() { Statement::Error(e) }
-            / i:expression() _ "=" _ e:expression() { Statement::Assign(i, e) }
-            / e:expression() { Statement::ExpressionStatement(e) }
+            / "break" { StatementKind::Break }
+            / "return" e:(ws() e:expression() { e })?  { StatementKind::Return(e) }
+            / "output" ws() e:expression() { StatementKind::Output(e) }
+            / "error" ws() e:expression() { StatementKind::Error(e) }
+            / i:expression() _ "=" _ e:expression() { StatementKind::Assign(i, e) }
+            / e:expression() { StatementKind::ExpressionStatement(e) }
 
         pub rule program() -> Program
             = fun:(_ f:function() _ { f })+ { Program { functions: fun }}
 
+        /// Like `program`, but also returns the spans of any text skipped because it couldn't be
+        /// parsed as a function. Only useful when `ctx.recover` is set.
+        pub rule program_recovering() -> (Program, Vec<Span>)
+            = items:(_ i:(f:function() { Ok(f) } / s:skipped_function() { Err(s) }) _ { i })+ {
+                let mut functions = vec![];
+                let mut skipped = vec![];
+                for item in items {
+                    match item {
+                        Ok(f) => functions.push(f),
+                        Err(s) => skipped.push(s),
+                    }
+                }
+                (Program { functions }, skipped)
+            }
+
+        // Used to find out why the text at a recovered position failed to parse. The trailing
+        // `[_]*` means the rest of the input doesn't have to parse for these to succeed.
+        pub rule function_prefix() = function() [_]*
+        pub rule statement_prefix() = statement() [_]*
+
         pub rule function() -> Function
-            = name:ident() _ "(" params:(i:(_ i:ident() _ { i }) ** "," { i })")" _ "{" _ body:statement_list()? _ "}" { Function { name, params, body: body.unwrap_or(vec![]) }}
+            = lo:position!() name:ident() _ "(" params:(i:(_ i:ident() _ { i }) ** "," { i })")" _ "{" _ body:statement_list()? _ "}" hi:position!() {
+                Function::new(name, params, body.unwrap_or_default(), ctx.span(lo, hi))
+            }
 
         pub rule statement() -> Statement
-            = _ s:statement_contents() _ ";" _ { s }
-            / _ "while" _ "(" _ cond: expression() _ ")" _ "{" _ then:statement_list()? _ "}" { Statement::While { cond, then }}
-            / _ "while" _ "(" _ cond: expression() _ ")" _ then: statement()? { Statement::While { cond, then: then.map(|t| vec![t]) }}
-            / _ "if" _ "(" _ cond:expression() _")" _ "{" _ then:statement_list()? _ "}" otherwise:(_ "else" _ "{" _ s:statement_list()? _ "}" { s.unwrap_or(vec![]) })? {
-                Statement::If { cond, then, otherwise }
+            = _ lo:position!() kind:statement_kind() hi:position!() { Statement::new(kind, ctx.span(lo, hi)) }
+
+        rule statement_kind() -> StatementKind
+            = s:statement_contents() _ ";" { s }
+            / "while" _ "(" _ cond: expression() _ ")" _ "{" _ then:statement_list()? _ "}" { StatementKind::While { cond, then }}
+            / "while" _ "(" _ cond: expression() _ ")" _ then: statement()? { StatementKind::While { cond, then: then.map(|t| vec![t]) }}
+            / "if" _ "(" _ cond:expression() _")" _ "{" _ then:statement_list()? _ "}" otherwise:(_ "else" _ "{" _ s:statement_list()? _ "}" { s.unwrap_or_default() })? {
+                StatementKind::If { cond, then, otherwise }
             }
-            / _ "if" _ "(" _ cond:expression() _")" _ then:(t:statement()? { t.map(|t| vec![t]) }) _ otherwise:(_ "else" _ s:statement()? { s.map(|s| vec![s] ).unwrap_or(vec![]) })? {
-                Statement::If { cond, then, otherwise }
+            / "if" _ "(" _ cond:expression() _")" _ then:(t:statement()? { t.map(|t| vec![t]) }) otherwise:(_ "else" _ s:statement()? { s.map(|s| vec![s] ).unwrap_or_default() })? {
+                StatementKind::If { cond, then, otherwise }
             }
-            / "{" l:statement_list() "}" { Statement::Block(l) }
+            / "{" l:statement_list() "}" { StatementKind::Block(l) }
 
+        /// Expressions, from the loosest binding operators to the tightest. Binary operators are all
+        /// left associative. Dereferencing binds tighter than field projection, so `*p.f` reads
+        /// the field `f` of the record `p` points to, but calls bind tighter still, so `*f(x)`
+        /// dereferences the result of the call. Prefix operators are tried at every level, so the
+        /// operand of `*` can be any unary expression, as in `*-a` or `*&a`.
         pub rule expression() -> Expression
             = precedence! {
-                l:@ _ "+" _ r:(@)  { Expression::BinaryExpression(BinOp::Plus , Box::new(l), Box::new(r)) }
-                l:@ _ "-" _ r:(@)  { Expression::BinaryExpression(BinOp::Minus , Box::new(l), Box::new(r)) }
+                l:(@) _ "==" _ r:@ { binary(BinOp::CompareEq, l, r) }
+                l:(@) _ ">" _ r:@ { binary(BinOp::CompareGt, l, r) }
                 --
-                l:@ _ "*" _ r:(@)  { Expression::BinaryExpression(BinOp::Times , Box::new(l), Box::new(r)) }
-                l:@ _ "/" _ r:(@)  { Expression::BinaryExpression(BinOp::Divide , Box::new(l), Box::new(r)) }
+                l:(@) _ "+" _ r:@ { binary(BinOp::Plus, l, r) }
+                l:(@) _ "-" _ r:@ { binary(BinOp::Minus, l, r) }
                 --
-                l:@ _ "==" _ r:(@) { Expression::BinaryExpression(BinOp::CompareEq,  Box::new(l), Box::new(r)) }
-                l:@ _ ">" _ r:(@) { Expression::BinaryExpression(BinOp::CompareGt,  Box::new(l), Box::new(r)) }
+                l:(@) _ "*" _ r:@ { binary(BinOp::Times, l, r) }
+                l:(@) _ "/" _ r:@ { binary(BinOp::Divide, l, r) }
                 --
-                f:@ _ "(" _ e:( _ e:expression() _ { e }) ** "," _ ")" { Expression::Call(Box::new(f), e.into_iter().map(|e| Box::new(e)).collect())}
+                lo:position!() op:$("&" / "-") _ e:(@) {
+                    let span = ctx.span(lo, lo).to(e.span);
+                    let op = match op {
+                        "&" => UnOp::AddressOf,
+                        "-" => UnOp::Negate,
+                        _ => unreachable!()
+                    };
+                    Expression::new(ExpressionKind::UnaryExpression(op, Box::new(e)), span)
+                }
+                lo:position!() "alloc" ws() e:(@) {
+                    let span = ctx.span(lo, lo).to(e.span);
+                    Expression::new(ExpressionKind::Alloc(Box::new(e)), span)
+                }
                 --
-                op:$(['&' | '*' | '-']) _ e:@ {
-                    Expression::UnaryExpression(
-                        match op {
-                            "&" => UnOp::AddressOf,
-                            "*" => UnOp::Dereference,
-                            "-" => UnOp::Negate,
-                            _ => unreachable!()
-                        },
-                        Box::new(e))
+                e:(@) i:("." i:ident() { i })+ {
+                    let span = e.span.to(i.last().unwrap().span);
+                    Expression::new(ExpressionKind::Projection(Box::new(e), i), span)
                 }
                 --
-                "alloc" ws() e:@ { Expression::Alloc(Box::new(e)) }
+                lo:position!() "*" _ e:(@) {
+                    let span = ctx.span(lo, lo).to(e.span);
+                    Expression::new(ExpressionKind::UnaryExpression(UnOp::Dereference, Box::new(e)), span)
+                }
                 --
-                e:@ i:("." i:ident() { i })+ { Expression::Projection(Box::new(e), i) }
+                f:(@) _ "(" _ e:( _ e:expression() _ { e }) ** "," _ ")" hi:position!() {
+                    let span = f.span.to(ctx.span(hi, hi));
+                    Expression::new(ExpressionKind::Call(Box::new(f), e.into_iter().map(Box::new).collect()), span)
+                }
                 --
                 a:atom() { a }
 
             }
         pub rule atom() -> Expression
             = number()
-            / id:ident() { Expression::IdentReference(id) }
+            / lo:position!() "input" !ident_char() hi:position!() { Expression::new(ExpressionKind::Input, ctx.span(lo, hi)) }
+            / lo:position!() "null" !ident_char() hi:position!() { Expression::new(ExpressionKind::Null, ctx.span(lo, hi)) }
+            / lo:position!() id:ident() hi:position!() { Expression::new(ExpressionKind::IdentReference(id), ctx.span(lo, hi)) }
             / r:rec() { r }
-            / "(" e:expression() ")" { e }
+            / lo:position!() "(" e:expression() ")" hi:position!() { Expression { span: ctx.span(lo, hi), ..e } }
 
         pub rule rec() -> Expression
-            = "{" fields:(_ i:ident() _ ":" _ e:expression() _ { (i, e) }) ** "," "}" { Expression::Record(fields) }
+            = lo:position!() "{" fields:(_ i:ident() _ ":" _ e:expression() _ { (i, e) }) ** "," "}" hi:position!() {
+                Expression::new(ExpressionKind::Record(fields), ctx.span(lo, hi))
+            }
 
+        // A literal too big for an `i64` fails at its start, so the error points at the literal.
         pub rule number() -> Expression
-            = n:$(['0'..='9']+) { Expression::Number(n.parse().unwrap()) }
+            = lo:position!() n:&digits() value:({? n.parse().or(Err(NUMBER_OUT_OF_RANGE)) }) quiet!{digits()} hi:position!() {
+                Expression::new(ExpressionKind::Number(value), ctx.span(lo, hi))
+            }
+            / expected!("number")
+
+        rule digits() -> &'input str
+            = $(['0'..='9']+)
+
+        rule ident_char() = ['A'..='Z' | 'a'..='z' | '0'..='9' | '_' ]
+
+        /// Words that can't be used as identifiers.
+        rule keyword()
+            = ("alloc" / "break" / "else" / "error" / "if" / "input" / "null" / "output" / "return" / "var" / "while") !ident_char()
 
         pub rule ident() -> Ident
-            = id:$(['A'..='Z' | 'a'..='z']['A'..='Z' | 'a'..='z' | '0'..='9' | '_' ]*) { Ident(id.into()) }
+            = quiet!{!keyword() lo:position!() id:$(['A'..='Z' | 'a'..='z'] ident_char()*) hi:position!() { Ident::new(id, ctx.span(lo, hi)) }}
+            / expected!("identifier")
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::source_map::{SourceMap, Span};
+
+    fn ctx() -> ParseContext {
+        ParseContext::new(0)
+    }
+
+    #[test]
+    fn test_number_out_of_range() {
+        let error = tip_parser::expression("99999999999999999999", &ctx()).unwrap_err();
+        assert_eq!(error.location.offset, 0);
+        assert!(error.expected.tokens().any(|t| t == NUMBER_OUT_OF_RANGE));
+        assert!(tip_parser::expression("9223372036854775807", &ctx()).is_ok());
+    }
+
     #[test]
     fn test_parse_exprs() {
         assert_eq!(
-            tip_parser::expression("1 + 2 * 3"),
-            Ok(Expression::BinaryExpression(
+            tip_parser::expression("1 + 2 * 3", &ctx()),
+            Ok(Expression::from(ExpressionKind::BinaryExpression(
                 BinOp::Plus,
-                Box::new(Expression::Number(1)),
-                Box::new(Expression::BinaryExpression(
+                Box::new(Expression::from(ExpressionKind::Number(1))),
+                Box::new(Expression::from(ExpressionKind::BinaryExpression(
                     BinOp::Times,
-                    Box::new(Expression::Number(2)),
-                    Box::new(Expression::Number(3))
-                ))
-            ))
+                    Box::new(Expression::from(ExpressionKind::Number(2))),
+                    Box::new(Expression::from(ExpressionKind::Number(3)))
+                )))
+            )))
         );
         assert_eq!(
-            tip_parser::expression("(1 + 2) * 3"),
-            Ok(Expression::BinaryExpression(
+            tip_parser::expression("(1 + 2) * 3", &ctx()),
+            Ok(Expression::from(ExpressionKind::BinaryExpression(
                 BinOp::Times,
-                Box::new(Expression::BinaryExpression(
+                Box::new(Expression::from(ExpressionKind::BinaryExpression(
                     BinOp::Plus,
-                    Box::new(Expression::Number(1)),
-                    Box::new(Expression::Number(2))
-                )),
-                Box::new(Expression::Number(3)),
-            ))
+                    Box::new(Expression::from(ExpressionKind::Number(1))),
+                    Box::new(Expression::from(ExpressionKind::Number(2)))
+                ))),
+                Box::new(Expression::from(ExpressionKind::Number(3))),
+            )))
         );
         assert_eq!(
-            tip_parser::expression("1 - 2 / 3"),
-            Ok(Expression::BinaryExpression(
+            tip_parser::expression("1 - 2 / 3", &ctx()),
+            Ok(Expression::from(ExpressionKind::BinaryExpression(
                 BinOp::Minus,
-                Box::new(Expression::Number(1)),
-                Box::new(Expression::BinaryExpression(
+                Box::new(Expression::from(ExpressionKind::Number(1))),
+                Box::new(Expression::from(ExpressionKind::BinaryExpression(
                     BinOp::Divide,
-                    Box::new(Expression::Number(2)),
-                    Box::new(Expression::Number(3))
-                ))
-            ))
+                    Box::new(Expression::from(ExpressionKind::Number(2))),
+                    Box::new(Expression::from(ExpressionKind::Number(3)))
+                )))
+            )))
         );
         assert_eq!(
-            tip_parser::expression("(1 - 2) / 3"),
-            Ok(Expression::BinaryExpression(
+            tip_parser::expression("(1 - 2) / 3", &ctx()),
+            Ok(Expression::from(ExpressionKind::BinaryExpression(
                 BinOp::Divide,
-                Box::new(Expression::BinaryExpression(
+                Box::new(Expression::from(ExpressionKind::BinaryExpression(
                     BinOp::Minus,
-                    Box::new(Expression::Number(1)),
-                    Box::new(Expression::Number(2))
-                )),
-                Box::new(Expression::Number(3)),
-            ))
+                    Box::new(Expression::from(ExpressionKind::Number(1))),
+                    Box::new(Expression::from(ExpressionKind::Number(2)))
+                ))),
+                Box::new(Expression::from(ExpressionKind::Number(3))),
+            )))
         );
 
         assert_eq!(
-            tip_parser::expression("1 * 2 * 3"),
-            Ok(Expression::BinaryExpression(
+            tip_parser::expression("1 * 2 * 3", &ctx()),
+            Ok(Expression::from(ExpressionKind::BinaryExpression(
                 BinOp::Times,
-                Box::new(Expression::Number(1)),
-                Box::new(Expression::BinaryExpression(
+                Box::new(Expression::from(ExpressionKind::BinaryExpression(
                     BinOp::Times,
-                    Box::new(Expression::Number(2)),
-                    Box::new(Expression::Number(3)),
-                )),
-            ))
+                    Box::new(Expression::from(ExpressionKind::Number(1))),
+                    Box::new(Expression::from(ExpressionKind::Number(2))),
+                ))),
+                Box::new(Expression::from(ExpressionKind::Number(3))),
+            )))
         );
     }
+
+    /// Writes out an expression as an s-expression, making its structure explicit.
+    fn sexpr(e: &Expression) -> String {
+        let list = |head: &str, args: Vec<String>| format!("({} {})", head, args.join(" "));
+        match &e.kind {
+            ExpressionKind::Number(n) => n.to_string(),
+            ExpressionKind::IdentReference(id) => id.name.to_string(),
+            ExpressionKind::Input => "input".to_string(),
+            ExpressionKind::Null => "null".to_string(),
+            ExpressionKind::BinaryExpression(op, l, r) => {
+                let op = match op {
+                    BinOp::Plus => "+",
+                    BinOp::Minus => "-",
+                    BinOp::Times => "*",
+                    BinOp::Divide => "/",
+                    BinOp::CompareEq => "==",
+                    BinOp::CompareGt => ">",
+                };
+                list(op, vec![sexpr(l), sexpr(r)])
+            }
+            ExpressionKind::UnaryExpression(op, e) => {
+                let op = match op {
+                    UnOp::Negate => "neg",
+                    UnOp::AddressOf => "addr",
+                    UnOp::Dereference => "deref",
+                };
+                list(op, vec![sexpr(e)])
+            }
+            ExpressionKind::Alloc(e) => list("alloc", vec![sexpr(e)]),
+            ExpressionKind::Call(f, args) => list(
+                "call",
+                std::iter::once(f).chain(args).map(|e| sexpr(e)).collect(),
+            ),
+            ExpressionKind::Record(fields) => list(
+                "record",
+                fields
+                    .iter()
+                    .map(|(id, e)| format!("{}:{}", id.name, sexpr(e)))
+                    .collect(),
+            ),
+            ExpressionKind::Projection(e, ids) => list(
+                ".",
+                std::iter::once(sexpr(e))
+                    .chain(ids.iter().map(|id| id.name.to_string()))
+                    .collect(),
+            ),
+        }
+    }
+
+    #[test]
+    fn test_binary_operator_precedence() {
+        // Binary operators by precedence level, loosest first.
+        let levels: &[&[&str]] = &[&["==", ">"], &["+", "-"], &["*", "/"]];
+        let level_of = |op: &str| levels.iter().position(|l| l.contains(&op)).unwrap();
+        for &op1 in levels.concat().iter() {
+            for &op2 in levels.concat().iter() {
+                let src = format!("a {} b {} c", op1, op2);
+                // Everything is left associative, so the second operator only takes `b` as its left
+                // operand if it binds more tightly than the first.
+                let expected = if level_of(op2) > level_of(op1) {
+                    format!("({} a ({} b c))", op1, op2)
+                } else {
+                    format!("({} ({} a b) c)", op2, op1)
+                };
+                let parsed = tip_parser::expression(&src, &ctx()).unwrap();
+                assert_eq!(sexpr(&parsed), expected, "parsing `{}`", src);
+            }
+        }
+    }
+
+    #[test]
+    fn test_operator_precedence_table() {
+        let table = [
+            // Unary operators bind tighter than any binary operator.
+            ("-a * b", "(* (neg a) b)"),
+            ("a * -b", "(* a (neg b))"),
+            ("-a + b", "(+ (neg a) b)"),
+            ("-a == b", "(== (neg a) b)"),
+            ("&a == b", "(== (addr a) b)"),
+            ("*a * *b", "(* (deref a) (deref b))"),
+            ("*a / b", "(/ (deref a) b)"),
+            ("alloc a + b", "(+ (alloc a) b)"),
+            ("alloc a > b", "(> (alloc a) b)"),
+            ("a - -b", "(- a (neg b))"),
+            ("- -a", "(neg (neg a))"),
+            ("**a", "(deref (deref a))"),
+            ("&*a", "(addr (deref a))"),
+            ("-*a", "(neg (deref a))"),
+            ("alloc -a", "(alloc (neg a))"),
+            ("alloc *a", "(alloc (deref a))"),
+            ("alloc null", "(alloc null)"),
+            ("- f(a)", "(neg (call f a))"),
+            ("&a.f", "(addr (. a f))"),
+            ("-a.f", "(neg (. a f))"),
+            ("alloc a.f", "(alloc (. a f))"),
+            ("alloc f(a)", "(alloc (call f a))"),
+            ("*-a", "(deref (neg a))"),
+            ("*&a", "(deref (addr a))"),
+            ("*alloc a", "(deref (alloc a))"),
+            ("*-a.f", "(deref (neg (. a f)))"),
+            ("*&a.f", "(deref (addr (. a f)))"),
+            ("*-a * b", "(* (deref (neg a)) b)"),
+            // Dereferencing binds tighter than projection, but calls bind tighter than both.
+            ("*a.f", "(. (deref a) f)"),
+            ("*a.f.g", "(. (deref a) f g)"),
+            ("*(*a).f", "(. (deref (deref a)) f)"),
+            ("*(a).f", "(. (deref a) f)"),
+            ("*f(a)", "(deref (call f a))"),
+            ("(*f)(a)", "(call (deref f) a)"),
+            ("*f(a).g", "(. (deref (call f a)) g)"),
+            ("f(a).g", "(. (call f a) g)"),
+            ("a.f(b)", "(call (. a f) b)"),
+            ("f(a)(b)", "(call (call f a) b)"),
+            ("f(*a, b + c)", "(call f (deref a) (+ b c))"),
+            ("f(a) * g(b)", "(* (call f a) (call g b))"),
+            ("a.f + b.g", "(+ (. a f) (. b g))"),
+            ("{f: a + b}.f", "(. (record f:(+ a b)) f)"),
+            // Parentheses override everything.
+            ("(a + b) * c", "(* (+ a b) c)"),
+            ("a - (b - c)", "(- a (- b c))"),
+            ("-(a + b)", "(neg (+ a b))"),
+            ("(a > b) == c", "(== (> a b) c)"),
+            ("a > (b == c)", "(> a (== b c))"),
+            ("10 - 2 - 3", "(- (- 10 2) 3)"),
+            ("8 / 4 / 2", "(/ (/ 8 4) 2)"),
+            ("a * b > c", "(> (* a b) c)"),
+            ("input + 1 > 0", "(> (+ input 1) 0)"),
+        ];
+        for (src, expected) in table.iter() {
+            let parsed = tip_parser::expression(src, &ctx()).unwrap();
+            assert_eq!(&sexpr(&parsed), expected, "parsing `{}`", src);
+        }
+    }
     #[test]
     fn test_parse_ident() {
-        assert_eq!(tip_parser::ident("x"), Ok(Ident("x".to_string())));
-        assert_eq!(tip_parser::ident("y"), Ok(Ident("y".to_string())));
-        assert_eq!(tip_parser::ident("z"), Ok(Ident("z".to_string())));
-        assert_eq!(tip_parser::ident("xyz"), Ok(Ident("xyz".to_string())));
-        assert_eq!(tip_parser::ident("abc123"), Ok(Ident("abc123".to_string())));
+        assert_eq!(tip_parser::ident("x", &ctx()), Ok(Ident::from("x")));
+        assert_eq!(tip_parser::ident("y", &ctx()), Ok(Ident::from("y")));
+        assert_eq!(tip_parser::ident("z", &ctx()), Ok(Ident::from("z")));
+        assert_eq!(tip_parser::ident("xyz", &ctx()), Ok(Ident::from("xyz")));
+        assert_eq!(
+            tip_parser::ident("abc123", &ctx()),
+            Ok(Ident::from("abc123"))
+        );
         assert_eq!(
-            tip_parser::ident("abc_123"),
-            Ok(Ident("abc_123".to_string()))
+            tip_parser::ident("abc_123", &ctx()),
+            Ok(Ident::from("abc_123"))
         );
     }
 
     #[test]
     fn test_parse_var_decl() {
         assert_eq!(
-            tip_parser::statement("var x;"),
-            Ok(Statement::VarDecl(vec![Ident("x".to_string())]))
+            tip_parser::statement("var x;", &ctx()),
+            Ok(Statement::from(StatementKind::VarDecl(vec![Ident::from(
+                "x"
+            )])))
         );
         assert_eq!(
-            tip_parser::statement("var x, y, z;"),
-            Ok(Statement::VarDecl(
-                vec!["x", "y", "z"]
-                    .into_iter()
-                    .map(|s| Ident(s.to_string()))
-                    .collect()
-            ))
+            tip_parser::statement("var x, y, z;", &ctx()),
+            Ok(Statement::from(StatementKind::VarDecl(
+                vec!["x", "y", "z"].into_iter().map(Ident::from).collect()
+            )))
         );
         assert_eq!(
-            tip_parser::statement("var x,y,z;"),
-            Ok(Statement::VarDecl(
-                vec!["x", "y", "z"]
-                    .into_iter()
-                    .map(|s| Ident(s.to_string()))
-                    .collect()
-            ))
+            tip_parser::statement("var x,y,z;", &ctx()),
+            Ok(Statement::from(StatementKind::VarDecl(
+                vec!["x", "y", "z"].into_iter().map(Ident::from).collect()
+            )))
         );
         assert_eq!(
-            tip_parser::statement("var a_complex_name,y, result123;"),
-            Ok(Statement::VarDecl(
+            tip_parser::statement("var a_complex_name,y, result123;", &ctx()),
+            Ok(Statement::from(StatementKind::VarDecl(
                 vec!["a_complex_name", "y", "result123"]
                     .into_iter()
-                    .map(|s| Ident(s.to_string()))
+                    .map(Ident::from)
                     .collect()
-            ))
+            )))
         );
     }
 
     #[test]
     fn test_break() {
-        assert_eq!(tip_parser::statement("break;"), Ok(Statement::Break));
+        assert_eq!(
+            tip_parser::statement("break;", &ctx()),
+            Ok(Statement::from(StatementKind::Break))
+        );
     }
 
     #[test]
     fn test_return() {
         assert_eq!(
-            tip_parser::statement("return;"),
-            Ok(Statement::Return(None))
+            tip_parser::statement("return;", &ctx()),
+            Ok(Statement::from(StatementKind::Return(None)))
         );
         assert_eq!(
-            tip_parser::statement("return 123;"),
-            Ok(Statement::Return(Some(Expression::Number(123))))
+            tip_parser::statement("return 123;", &ctx()),
+            Ok(Statement::from(StatementKind::Return(Some(
+                Expression::from(ExpressionKind::Number(123))
+            ))))
         );
     }
 
     #[test]
     fn test_if() {
         assert_eq!(
-            tip_parser::statement("if (1) { } else { }"),
-            Ok(Statement::If {
-                cond: Expression::Number(1),
+            tip_parser::statement("if (1) { } else { }", &ctx()),
+            Ok(Statement::from(StatementKind::If {
+                cond: Expression::from(ExpressionKind::Number(1)),
                 then: None,
                 otherwise: Some(vec![])
-            })
+            }))
         );
         assert_eq!(
-            tip_parser::statement("if (1) { var x; } else { var y; }"),
-            Ok(Statement::If {
-                cond: Expression::Number(1),
-                then: Some(vec![Statement::VarDecl(vec![Ident("x".to_string())])]),
-                otherwise: Some(vec![Statement::VarDecl(vec![Ident("y".to_string())])]),
-            })
+            tip_parser::statement("if (1) { var x; } else { var y; }", &ctx()),
+            Ok(Statement::from(StatementKind::If {
+                cond: Expression::from(ExpressionKind::Number(1)),
+                then: Some(vec![Statement::from(StatementKind::VarDecl(vec![
+                    Ident::from("x")
+                ]))]),
+                otherwise: Some(vec![Statement::from(StatementKind::VarDecl(vec![
+                    Ident::from("y")
+                ]))]),
+            }))
         );
         assert_eq!(
-            tip_parser::statement("if (1) { var x; var y; } else { var a; var b; }"),
-            Ok(Statement::If {
-                cond: Expression::Number(1),
+            tip_parser::statement("if (1) { var x; var y; } else { var a; var b; }", &ctx()),
+            Ok(Statement::from(StatementKind::If {
+                cond: Expression::from(ExpressionKind::Number(1)),
                 then: Some(vec![
-                    Statement::VarDecl(vec![Ident("x".to_string())]),
-                    Statement::VarDecl(vec![Ident("y".to_string())])
+                    Statement::from(StatementKind::VarDecl(vec![Ident::from("x")])),
+                    Statement::from(StatementKind::VarDecl(vec![Ident::from("y")]))
                 ]),
                 otherwise: Some(vec![
-                    Statement::VarDecl(vec![Ident("a".to_string())]),
-                    Statement::VarDecl(vec![Ident("b".to_string())])
+                    Statement::from(StatementKind::VarDecl(vec![Ident::from("a")])),
+                    Statement::from(StatementKind::VarDecl(vec![Ident::from("b")]))
                 ]),
-            })
+            }))
         );
-        assert!(tip_parser::statement("if (1) var x; else var y;").is_ok());
-        assert_eq!(
-            tip_parser::statement("if (1) if (2) var x; else var y; else var z;"),
-            Ok(Statement::If {
-                cond: Expression::Number(1),
-                then: Some(vec![Statement::If {
-                    cond: Expression::Number(2),
-                    then: Some(vec![Statement::VarDecl(vec![Ident("x".to_string())])]),
-                    otherwise: Some(vec![Statement::VarDecl(vec![Ident("y".to_string())])]),
-                }]),
-                otherwise: Some(vec![Statement::VarDecl(vec![Ident("z".to_string())])]),
-            })
+        assert!(tip_parser::statement("if (1) var x; else var y;", &ctx()).is_ok());
+        assert_eq!(
+            tip_parser::statement("if (1) if (2) var x; else var y; else var z;", &ctx()),
+            Ok(Statement::from(StatementKind::If {
+                cond: Expression::from(ExpressionKind::Number(1)),
+                then: Some(vec![Statement::from(StatementKind::If {
+                    cond: Expression::from(ExpressionKind::Number(2)),
+                    then: Some(vec![Statement::from(StatementKind::VarDecl(vec![
+                        Ident::from("x")
+                    ]))]),
+                    otherwise: Some(vec![Statement::from(StatementKind::VarDecl(vec![
+                        Ident::from("y")
+                    ]))]),
+                })]),
+                otherwise: Some(vec![Statement::from(StatementKind::VarDecl(vec![
+                    Ident::from("z")
+                ]))]),
+            }))
         );
     }
 
     #[test]
     fn test_while() {
         assert_eq!(
-            tip_parser::statement("while (1) { var x; }"),
-            Ok(Statement::While {
-                cond: Expression::Number(1),
-                then: Some(vec![Statement::VarDecl(vec![Ident("x".to_string())])])
-            })
+            tip_parser::statement("while (1) { var x; }", &ctx()),
+            Ok(Statement::from(StatementKind::While {
+                cond: Expression::from(ExpressionKind::Number(1)),
+                then: Some(vec![Statement::from(StatementKind::VarDecl(vec![
+                    Ident::from("x")
+                ]))])
+            }))
         );
         assert_eq!(
-            tip_parser::statement("while (1) { var x; var y; }"),
-            Ok(Statement::While {
-                cond: Expression::Number(1),
+            tip_parser::statement("while (1) { var x; var y; }", &ctx()),
+            Ok(Statement::from(StatementKind::While {
+                cond: Expression::from(ExpressionKind::Number(1)),
                 then: Some(vec![
-                    Statement::VarDecl(vec![Ident("x".to_string())]),
-                    Statement::VarDecl(vec![Ident("y".to_string())])
+                    Statement::from(StatementKind::VarDecl(vec![Ident::from("x")])),
+                    Statement::from(StatementKind::VarDecl(vec![Ident::from("y")]))
                 ],)
-            })
+            }))
         );
         assert_eq!(
-            tip_parser::statement("while (1) var x;"),
-            Ok(Statement::While {
-                cond: Expression::Number(1),
-                then: Some(vec![Statement::VarDecl(vec![Ident("x".to_string())])])
-            })
+            tip_parser::statement("while (1) var x;", &ctx()),
+            Ok(Statement::from(StatementKind::While {
+                cond: Expression::from(ExpressionKind::Number(1)),
+                then: Some(vec![Statement::from(StatementKind::VarDecl(vec![
+                    Ident::from("x")
+                ]))])
+            }))
         );
     }
 
     #[test]
     fn test_output() {
         assert_eq!(
-            tip_parser::statement("output x;"),
-            Ok(Statement::Output(Expression::IdentReference(Ident(
-                "x".to_string()
+            tip_parser::statement("output x;", &ctx()),
+            Ok(Statement::from(StatementKind::Output(Expression::from(
+                ExpressionKind::IdentReference(Ident::from("x"))
             ))))
         );
     }
@@ -329,46 +590,50 @@ mod tests {
     #[test]
     fn test_function() {
         assert_eq!(
-            tip_parser::function("f() { return 0; }"),
-            Ok(Function {
-                name: Ident("f".to_string()),
-                params: vec![],
-                body: vec![Statement::Return(Some(Expression::Number(0)))],
-            })
+            tip_parser::function("f() { return 0; }", &ctx()),
+            Ok(Function::new(
+                Ident::from("f"),
+                vec![],
+                vec![Statement::from(StatementKind::Return(Some(
+                    Expression::from(ExpressionKind::Number(0))
+                )))],
+                Span::DUMMY,
+            ))
         );
         assert_eq!(
-            tip_parser::function("g(x, y, z) { return 1; }"),
-            Ok(Function {
-                name: Ident("g".to_string()),
-                params: vec![
-                    Ident("x".to_string()),
-                    Ident("y".to_string()),
-                    Ident("z".to_string())
-                ],
-                body: vec![Statement::Return(Some(Expression::Number(1)))],
-            })
+            tip_parser::function("g(x, y, z) { return 1; }", &ctx()),
+            Ok(Function::new(
+                Ident::from("g"),
+                vec![Ident::from("x"), Ident::from("y"), Ident::from("z")],
+                vec![Statement::from(StatementKind::Return(Some(
+                    Expression::from(ExpressionKind::Number(1))
+                )))],
+                Span::DUMMY,
+            ))
         );
     }
     #[test]
     fn test_program() {
         assert_eq!(
-            tip_parser::program("f() { return 0; } g(x, y, z) { return 1; }"),
+            tip_parser::program("f() { return 0; } g(x, y, z) { return 1; }", &ctx()),
             Ok(Program {
                 functions: vec![
-                    Function {
-                        params: vec![],
-                        name: Ident("f".to_string()),
-                        body: vec![Statement::Return(Some(Expression::Number(0)))],
-                    },
-                    Function {
-                        name: Ident("g".to_string()),
-                        params: vec![
-                            Ident("x".to_string()),
-                            Ident("y".to_string()),
-                            Ident("z".to_string())
-                        ],
-                        body: vec![Statement::Return(Some(Expression::Number(1)))],
-                    }
+                    Function::new(
+                        Ident::from("f"),
+                        vec![],
+                        vec![Statement::from(StatementKind::Return(Some(
+                            Expression::from(ExpressionKind::Number(0))
+                        )))],
+                        Span::DUMMY,
+                    ),
+                    Function::new(
+                        Ident::from("g"),
+                        vec![Ident::from("x"), Ident::from("y"), Ident::from("z")],
+                        vec![Statement::from(StatementKind::Return(Some(
+                            Expression::from(ExpressionKind::Number(1))
+                        )))],
+                        Span::DUMMY,
+                    )
                 ]
             })
         );
@@ -377,70 +642,86 @@ mod tests {
     #[test]
     fn test_call() {
         assert_eq!(
-            tip_parser::expression("f()"),
-            Ok(Expression::Call(
-                Box::new(Expression::IdentReference(Ident("f".to_string()))),
+            tip_parser::expression("f()", &ctx()),
+            Ok(Expression::from(ExpressionKind::Call(
+                Box::new(Expression::from(ExpressionKind::IdentReference(
+                    Ident::from("f")
+                ))),
                 vec![],
-            ))
+            )))
         );
         assert_eq!(
-            tip_parser::expression("f(a, b, c)"),
-            Ok(Expression::Call(
-                Box::new(Expression::IdentReference(Ident("f".to_string()))),
+            tip_parser::expression("f(a, b, c)", &ctx()),
+            Ok(Expression::from(ExpressionKind::Call(
+                Box::new(Expression::from(ExpressionKind::IdentReference(
+                    Ident::from("f")
+                ))),
                 ["a", "b", "c"]
                     .iter()
-                    .map(|x| Box::new(Expression::IdentReference(Ident(x.to_string()))))
+                    .map(
+                        |x| Box::new(Expression::from(ExpressionKind::IdentReference(
+                            Ident::from(*x)
+                        )))
+                    )
                     .collect()
-            ))
+            )))
         );
         assert_eq!(
-            tip_parser::expression("(f)()"),
-            Ok(Expression::Call(
-                Box::new(Expression::IdentReference(Ident("f".to_string()))),
+            tip_parser::expression("(f)()", &ctx()),
+            Ok(Expression::from(ExpressionKind::Call(
+                Box::new(Expression::from(ExpressionKind::IdentReference(
+                    Ident::from("f")
+                ))),
                 vec![],
-            ))
+            )))
         );
 
         assert_eq!(
-            tip_parser::expression("f()()"),
-            Ok(Expression::Call(
-                Box::new(Expression::Call(
-                    Box::new(Expression::IdentReference(Ident("f".to_string()))),
+            tip_parser::expression("f()()", &ctx()),
+            Ok(Expression::from(ExpressionKind::Call(
+                Box::new(Expression::from(ExpressionKind::Call(
+                    Box::new(Expression::from(ExpressionKind::IdentReference(
+                        Ident::from("f")
+                    ))),
                     vec![],
-                )),
+                ))),
                 vec![]
-            ),)
+            )),)
         );
     }
 
     #[test]
     fn test_assign() {
         assert_eq!(
-            tip_parser::statement("n = 42;"),
-            Ok(Statement::Assign(
-                Expression::IdentReference(Ident("n".to_string())),
-                Expression::Number(42)
-            ))
+            tip_parser::statement("n = 42;", &ctx()),
+            Ok(Statement::from(StatementKind::Assign(
+                Expression::from(ExpressionKind::IdentReference(Ident::from("n"))),
+                Expression::from(ExpressionKind::Number(42))
+            )))
         );
     }
     #[test]
     fn test_pointers() {
         assert_eq!(
-            tip_parser::expression("f(*x)"),
-            Ok(Expression::Call(
-                Box::new(Expression::IdentReference(Ident("f".to_string()))),
-                vec![Box::new(Expression::UnaryExpression(
+            tip_parser::expression("f(*x)", &ctx()),
+            Ok(Expression::from(ExpressionKind::Call(
+                Box::new(Expression::from(ExpressionKind::IdentReference(
+                    Ident::from("f")
+                ))),
+                vec![Box::new(Expression::from(ExpressionKind::UnaryExpression(
                     UnOp::Dereference,
-                    Box::new(Expression::IdentReference(Ident("x".to_string())))
-                ))]
-            ))
+                    Box::new(Expression::from(ExpressionKind::IdentReference(
+                        Ident::from("x")
+                    )))
+                )))]
+            )))
         );
     }
 
     #[test]
     fn test_comment() {
-        assert!(tip_parser::comment("// This is a comment").is_ok());
-        assert!(tip_parser::comment("/* This is a block comment */").is_ok());
+        assert!(tip_parser::comment("// This is a comment", &ctx()).is_ok());
+        assert!(tip_parser::comment("/* This is a block comment */", &ctx()).is_ok());
     }
 
     #[test]
@@ -455,12 +736,100 @@ f() {
 /* This is a block comment */
 h() /* This is a block comment in an awkward place */ { }\
         ";
-        let result = tip_parser::program(src);
+        let result = tip_parser::program(src, &ctx());
         dbg!(&result);
         assert!(result.is_ok());
     }
     #[test]
     fn parse_error() {
-        assert_eq!(tip_parser::statement("error 1;"), Ok(Statement::Error(Expression::Number(1))));
+        assert_eq!(
+            tip_parser::statement("error 1;", &ctx()),
+            Ok(Statement::from(StatementKind::Error(Expression::from(
+                ExpressionKind::Number(1)
+            ))))
+        );
+    }
+
+    #[test]
+    fn test_spans() {
+        let src = "x = (a + 1) * f(b);";
+        let stmt = tip_parser::statement(src, &ctx()).unwrap();
+        assert_eq!(stmt.span, Span::new(0, src.len()));
+        let (lhs, rhs) = match stmt.kind {
+            StatementKind::Assign(lhs, rhs) => (lhs, rhs),
+            _ => panic!("expected an assignment"),
+        };
+        assert_eq!(&src[lhs.span.lo..lhs.span.hi], "x");
+        assert_eq!(&src[rhs.span.lo..rhs.span.hi], "(a + 1) * f(b)");
+        match rhs.kind {
+            ExpressionKind::BinaryExpression(_, l, r) => {
+                assert_eq!(&src[l.span.lo..l.span.hi], "(a + 1)");
+                assert_eq!(&src[r.span.lo..r.span.hi], "f(b)");
+            }
+            _ => panic!("expected a binary expression"),
+        }
+    }
+
+    #[test]
+    fn test_spans_in_source_map() {
+        let mut sm = SourceMap::new();
+        sm.add_file("a.tip", "f() { return 0; }".to_string());
+        let file = sm.add_file(
+            "b.tip",
+            "main() {
+    var x;
+    return x;
+}"
+            .to_string(),
+        );
+        let program = crate::tip_parser::parse_file(file).unwrap();
+        let main = &program.functions[0];
+        assert_eq!(sm.span_to_string(main.span), "b.tip:1:1");
+        assert_eq!(sm.span_to_string(main.body[1].span), "b.tip:3:5");
+        match &main.body[0].kind {
+            StatementKind::VarDecl(ids) => assert_eq!(sm.span_to_string(ids[0].span), "b.tip:2:9"),
+            _ => panic!("expected a declaration"),
+        }
+    }
+
+    #[test]
+    fn test_input_and_null() {
+        assert_eq!(
+            tip_parser::expression("input", &ctx()),
+            Ok(Expression::from(ExpressionKind::Input))
+        );
+        assert_eq!(
+            tip_parser::expression("null", &ctx()),
+            Ok(Expression::from(ExpressionKind::Null))
+        );
+        assert_eq!(
+            tip_parser::statement("x = alloc null;", &ctx()),
+            Ok(Statement::from(StatementKind::Assign(
+                Expression::from(ExpressionKind::IdentReference(Ident::from("x"))),
+                Expression::from(ExpressionKind::Alloc(Box::new(Expression::from(
+                    ExpressionKind::Null
+                ))))
+            )))
+        );
+        // Identifiers which merely start with a keyword are fine.
+        assert_eq!(
+            tip_parser::expression("inputs", &ctx()),
+            Ok(Expression::from(ExpressionKind::IdentReference(
+                Ident::from("inputs")
+            )))
+        );
+        assert_eq!(
+            tip_parser::ident("nullable", &ctx()),
+            Ok(Ident::from("nullable"))
+        );
+    }
+
+    #[test]
+    fn test_keywords_are_not_identifiers() {
+        for kw in &["input", "null", "while", "alloc"] {
+            assert!(tip_parser::ident(kw, &ctx()).is_err());
+            assert!(tip_parser::statement(&format!("var {};", kw), &ctx()).is_err());
+            assert!(tip_parser::function(&format!("{}() {{ }}", kw), &ctx()).is_err());
+        }
     }
 }
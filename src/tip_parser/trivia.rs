@@ -0,0 +1,214 @@
+use crate::ast::{Body, Comment, Function, NodeId, Program, Statement, StatementKind, Trivia};
+use crate::source_map::{SourceFile, Span};
+
+/// A function or statement that comments can be attached to.
+struct Node<'a> {
+    id: NodeId,
+    span: Span,
+    bodies: Vec<NodeBody<'a>>,
+}
+
+/// One of a node's statement lists, along with the source it covers (including its braces), so
+/// that comments inside it end up inside it too.
+struct NodeBody<'a> {
+    body: Body,
+    stmts: &'a [Statement],
+    span: Span,
+}
+
+/// Attaches each comment in `file` to the node it most likely describes.
+///
+/// A comment sharing a line with the end of the node before it trails that node; any other comment
+/// leads the node after it. Comments with no node after them in their statement list go at the end
+/// of the list, and comments inside a node but outside its bodies go with the node's first line.
+pub(super) fn attach_comments(
+    file: &SourceFile,
+    program: &Program,
+    comments: Vec<Comment>,
+) -> Trivia {
+    let mut attacher = Attacher {
+        file,
+        comments: &comments,
+        trivia: Trivia::default(),
+    };
+    let nodes: Vec<_> = program
+        .functions
+        .iter()
+        .map(|f| attacher.function_node(f))
+        .collect();
+    attacher.attach(&nodes, Span::new(file.start_pos, file.end_pos()), None);
+    attacher.trivia
+}
+
+struct Attacher<'a> {
+    file: &'a SourceFile,
+    /// Sorted by position.
+    comments: &'a [Comment],
+    trivia: Trivia,
+}
+
+impl<'a> Attacher<'a> {
+    fn text(&self, lo: usize, hi: usize) -> &'a str {
+        self.file.source_text(Span::new(lo, hi))
+    }
+
+    fn same_line(&self, lo: usize, hi: usize) -> bool {
+        !self.text(lo, hi).contains('\n')
+    }
+
+    /// The comments starting in `[lo, hi)`.
+    fn comments_in(&self, lo: usize, hi: usize) -> &'a [Comment] {
+        let start = self.comments.partition_point(|c| c.span.lo < lo);
+        let end = self.comments.partition_point(|c| c.span.lo < hi);
+        &self.comments[start..end]
+    }
+
+    /// The position of the first `token` in `[lo, hi)` that isn't part of a comment.
+    fn find(&self, lo: usize, hi: usize, token: &str) -> Option<usize> {
+        let mut text = self.text(lo, hi).as_bytes().to_vec();
+        for c in self.comments_in(lo, hi) {
+            for b in &mut text[c.span.lo - lo..c.span.hi.min(hi) - lo] {
+                *b = b' ';
+            }
+        }
+        text.windows(token.len())
+            .position(|w| w == token.as_bytes())
+            .map(|idx| lo + idx)
+    }
+
+    fn function_node(&self, f: &'a Function) -> Node<'a> {
+        let open = self
+            .find(f.name.span.hi, f.span.hi, "{")
+            .expect("function without a body");
+        Node {
+            id: f.id,
+            span: f.span,
+            bodies: vec![NodeBody {
+                body: Body::Main,
+                stmts: &f.body,
+                span: Span::new(open, f.span.hi),
+            }],
+        }
+    }
+
+    fn statement_node(&self, s: &'a Statement) -> Node<'a> {
+        let main = |stmts, lo| NodeBody {
+            body: Body::Main,
+            stmts,
+            span: Span::new(lo, s.span.hi),
+        };
+        let bodies = match &s.kind {
+            StatementKind::If {
+                cond,
+                then,
+                otherwise,
+            } => {
+                let then = then.as_deref().unwrap_or_default();
+                match otherwise {
+                    None => vec![main(then, cond.span.hi)],
+                    Some(otherwise) => {
+                        let then_end = then.last().map_or(cond.span.hi, |t| t.span.hi);
+                        let else_pos = self
+                            .find(then_end, s.span.hi, "else")
+                            .expect("`if` with a body for `else` but no `else`");
+                        vec![
+                            NodeBody {
+                                body: Body::Main,
+                                stmts: then,
+                                span: Span::new(cond.span.hi, else_pos),
+                            },
+                            NodeBody {
+                                body: Body::Else,
+                                stmts: otherwise,
+                                span: Span::new(else_pos, s.span.hi),
+                            },
+                        ]
+                    }
+                }
+            }
+            StatementKind::While { cond, then } => {
+                vec![main(then.as_deref().unwrap_or_default(), cond.span.hi)]
+            }
+            StatementKind::Block(body) => vec![main(body, s.span.lo)],
+            _ => vec![],
+        };
+        Node {
+            id: s.id,
+            span: s.span,
+            bodies,
+        }
+    }
+
+    /// Attaches the comments in `region` to `nodes`, the list of functions or statements it
+    /// contains, and then recursively to the nodes in their bodies. `owner` is the node and body
+    /// that `region` belongs to, if it isn't the whole file.
+    fn attach(&mut self, nodes: &[Node<'a>], region: Span, owner: Option<(NodeId, Body)>) {
+        for c in self.comments_in(region.lo, region.hi) {
+            let pos = c.span.lo;
+            if let Some(node) = nodes.iter().find(|n| n.span.lo <= pos && pos < n.span.hi) {
+                // Comments in a body are dealt with when recursing into it.
+                if node
+                    .bodies
+                    .iter()
+                    .all(|b| pos < b.span.lo || b.span.hi <= pos)
+                {
+                    let list = if node.bodies.is_empty() {
+                        self.trivia.trailing.entry(node.id).or_default()
+                    } else {
+                        self.trivia
+                            .body_start
+                            .entry((node.id, Body::Main))
+                            .or_default()
+                    };
+                    list.push(c.clone());
+                }
+                continue;
+            }
+            let prev = nodes.iter().rev().find(|n| n.span.hi <= pos);
+            let next = nodes.iter().find(|n| n.span.lo >= c.span.hi);
+            let list = match (prev, next, owner) {
+                (Some(prev), _, _) if self.same_line(prev.span.hi, pos) => {
+                    self.trivia.trailing.entry(prev.id).or_default()
+                }
+                (None, _, Some(owner)) if self.same_line(region.lo, pos) => {
+                    self.trivia.body_start.entry(owner).or_default()
+                }
+                (_, Some(next), _) => self.trivia.leading.entry(next.id).or_default(),
+                (_, None, Some(owner)) => self.trivia.body_end.entry(owner).or_default(),
+                (_, None, None) => &mut self.trivia.end,
+            };
+            list.push(c.clone());
+        }
+
+        if owner.is_some() {
+            for pair in nodes.windows(2) {
+                let (prev, next) = (&pair[0], &pair[1]);
+                let start = self
+                    .trivia
+                    .leading
+                    .get(&next.id)
+                    .map_or(next.span.lo, |c| c[0].span.lo);
+                if has_blank_line(self.text(prev.span.hi, start)) {
+                    self.trivia.blank_line_before.insert(next.id);
+                }
+            }
+        }
+
+        for node in nodes {
+            for body in &node.bodies {
+                let children: Vec<_> = body.stmts.iter().map(|s| self.statement_node(s)).collect();
+                self.attach(&children, body.span, Some((node.id, body.body)));
+            }
+        }
+    }
+}
+
+/// Whether `text` has a line with nothing but whitespace on it, not counting its first and last
+/// lines, which are shared with the code around it.
+fn has_blank_line(text: &str) -> bool {
+    let lines: Vec<_> = text.split('\n').collect();
+    lines.len() > 2
+        && lines[1..lines.len() - 1]
+            .iter()
+            .any(|l| l.trim().is_empty())
+}
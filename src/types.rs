@@ -0,0 +1,732 @@
+//! Type inference.
+//!
+//! Every expression and declaration gets a type variable, each construct constrains the variables
+//! of its parts (`*e` makes `e` a pointer to the type of `*e`, a call makes the callee a function
+//! of its arguments, and so on), and the constraints are solved by unification as they're
+//! generated. TIP's types are `int`, pointers, functions and records; a program is well-typed if
+//! the constraints are solvable.
+//!
+//! A record type has a type for every field name used anywhere in the program, with the fields the
+//! record doesn't have typed as absent, so that projecting a field a record doesn't have is a type
+//! error. The values of fields can't be records themselves.
+//!
+//! Types may be recursive: a linked list's cells are pointers to cells, so their type is the
+//! infinite `&&&...`, written `μα.&α`. Unification works on a graph of type terms, where a cycle is
+//! a recursive type, so it never needs to expand one.
+//!
+//! By default types are monomorphic: a function has one type, shared by all of its uses. In
+//! `Mode::Polymorphic`, each use of a function gets its own instance of the function's type.
+
+use crate::ast::visit::{self, Visitor};
+use crate::ast::{
+    BinOp, Expression, ExpressionKind, Function, Ident, NodeId, NodeMap, Program, Statement,
+    StatementKind, UnOp,
+};
+use crate::diagnostic::Diagnostic;
+use crate::resolve::{DeclKind, Resolution};
+use crate::source_map::Span;
+use crate::symbol::Symbol;
+use petgraph::algo::tarjan_scc;
+use petgraph::graph::DiGraph;
+use std::collections::hash_map::Entry;
+use std::collections::{btree_map, BTreeMap, HashSet};
+use std::fmt;
+
+mod dump;
+mod unify;
+
+pub use dump::{annotate, dump};
+use unify::{Mismatch, Term, TypeVar, Unifier};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Type {
+    Int,
+    Pointer(Box<Type>),
+    Function(Vec<Type>, Box<Type>),
+    /// A record type, with the fields it may have sorted by name. Fields it's known not to have
+    /// are left out.
+    Record(Vec<(Symbol, Type)>),
+    /// The type of a field that a record doesn't have.
+    Absent,
+    /// A type that isn't constrained, and so could be anything. Variables with the same number are
+    /// the same type.
+    Var(u32),
+    /// The recursive type `μα.t`, the type `t` with every `Var(α)` in it standing for the whole
+    /// type again.
+    Recursive(u32, Box<Type>),
+}
+
+impl fmt::Display for Type {
+    /// Prints the type in TIP's notation, naming its variables `α`, `β`, ... in order of
+    /// appearance.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.write(f, &mut VarNames::default())
+    }
+}
+
+/// Names for type variables, shared between types that are printed together so that the same
+/// variable always gets the same name.
+#[derive(Debug, Default)]
+pub struct VarNames(Vec<u32>);
+
+impl VarNames {
+    fn write(&mut self, out: &mut impl fmt::Write, v: u32) -> fmt::Result {
+        let idx = self.0.iter().position(|&x| x == v).unwrap_or_else(|| {
+            self.0.push(v);
+            self.0.len() - 1
+        });
+        // No μ, which is used for recursive types, and no ο, which looks like an o.
+        const NAMES: &[char] = &[
+            'α', 'β', 'γ', 'δ', 'ε', 'ζ', 'η', 'θ', 'ι', 'κ', 'λ', 'ν', 'ξ', 'π', 'ρ', 'σ', 'τ',
+            'υ', 'φ', 'χ', 'ψ', 'ω',
+        ];
+        out.write_char(NAMES[idx % NAMES.len()])?;
+        if idx >= NAMES.len() {
+            write!(out, "{}", idx / NAMES.len())?;
+        }
+        Ok(())
+    }
+}
+
+impl Type {
+    /// Prints the type like `to_string`, but naming its variables with `names`.
+    pub fn to_string_with(&self, names: &mut VarNames) -> String {
+        let mut out = String::new();
+        self.write(&mut out, names)
+            .expect("writing to a String can't fail");
+        out
+    }
+
+    fn write(&self, out: &mut impl fmt::Write, names: &mut VarNames) -> fmt::Result {
+        match self {
+            Type::Int => out.write_str("int"),
+            Type::Absent => out.write_str("◇"),
+            Type::Pointer(t) => {
+                out.write_str("&")?;
+                if let Type::Function(..) = **t {
+                    out.write_str("(")?;
+                    t.write(out, names)?;
+                    out.write_str(")")
+                } else {
+                    t.write(out, names)
+                }
+            }
+            Type::Function(params, ret) => {
+                out.write_str("(")?;
+                for (idx, param) in params.iter().enumerate() {
+                    if idx > 0 {
+                        out.write_str(", ")?;
+                    }
+                    param.write(out, names)?;
+                }
+                out.write_str(") -> ")?;
+                ret.write(out, names)
+            }
+            Type::Record(fields) => {
+                out.write_str("{")?;
+                for (idx, (name, t)) in fields.iter().enumerate() {
+                    if idx > 0 {
+                        out.write_str(", ")?;
+                    }
+                    write!(out, "{}: ", name)?;
+                    t.write(out, names)?;
+                }
+                out.write_str("}")
+            }
+            Type::Var(v) => names.write(out, *v),
+            Type::Recursive(v, t) => {
+                out.write_str("μ")?;
+                names.write(out, *v)?;
+                out.write_str(".")?;
+                t.write(out, names)
+            }
+        }
+    }
+}
+
+/// The inferred types of a program's expressions and declarations.
+#[derive(Debug, Default)]
+pub struct Types {
+    /// The type of each expression, keyed by its id.
+    pub exprs: NodeMap<Type>,
+    /// The type of each function, parameter and local, keyed by the id of its declaring identifier.
+    pub decls: NodeMap<Type>,
+}
+
+impl Types {
+    pub fn expr(&self, e: &Expression) -> Option<&Type> {
+        self.exprs.get(&e.id)
+    }
+
+    /// The type of the function or variable declared by `id`.
+    pub fn decl(&self, id: &Ident) -> Option<&Type> {
+        self.decls.get(&id.id)
+    }
+}
+
+/// How the types of functions are inferred.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// Every use of a function has the same type.
+    Monomorphic,
+    /// Functions are generalised over the type variables in their types, and each use of a
+    /// function gets a fresh instance of its type, as in Hindley–Milner let-polymorphism.
+    ///
+    /// A function is generalised once it and every function it uses have been checked, so
+    /// functions are checked a strongly connected component of the call graph at a time. Within
+    /// their own component, recursive functions are monomorphic.
+    Polymorphic,
+}
+
+/// Infers the type of every expression and declaration in `program`, which must have been
+/// resolved without errors. Reports a diagnostic for every constraint that can't be satisfied.
+pub fn infer(program: &Program, resolution: &Resolution, mode: Mode) -> (Types, Vec<Diagnostic>) {
+    let mut infer = Infer {
+        unifier: Unifier::default(),
+        resolution,
+        decls: NodeMap::default(),
+        exprs: NodeMap::default(),
+        returns: NodeMap::default(),
+        generalised: HashSet::new(),
+        fields: field_names(program),
+        field_values: vec![],
+        projections: vec![],
+        ret: None,
+        diagnostics: vec![],
+    };
+    // Give every function its type first, so that calls can be checked before the function they
+    // call.
+    for f in &program.functions {
+        let params = f.params.iter().map(|p| infer.decl(p.id)).collect();
+        let ret = infer.unifier.var();
+        let ty = infer.unifier.term(Term::Function(params, ret), f.name.span);
+        infer.decls.insert(f.name.id, ty);
+        infer.returns.insert(f.id, ret);
+    }
+    match mode {
+        Mode::Monomorphic => infer.visit_program(program),
+        Mode::Polymorphic => {
+            for component in call_graph_components(program, resolution) {
+                for f in &component {
+                    infer.visit_function(f);
+                }
+                infer
+                    .generalised
+                    .extend(component.iter().map(|f| f.name.id));
+            }
+        }
+    }
+
+    infer.check_fields();
+
+    let mut u = infer.unifier;
+    let mut resolve = |vars: NodeMap<TypeVar>| {
+        vars.into_iter()
+            .map(|(id, v)| (id, u.resolve(v)))
+            .collect::<NodeMap<_>>()
+    };
+    let types = Types {
+        exprs: resolve(infer.exprs),
+        decls: resolve(infer.decls),
+    };
+    (types, infer.diagnostics)
+}
+
+/// Every field name used in `program`.
+fn field_names(program: &Program) -> Vec<Symbol> {
+    #[derive(Default)]
+    struct Fields(Vec<Symbol>);
+
+    impl<'ast> Visitor<'ast> for Fields {
+        fn visit_field(&mut self, id: &'ast Ident) {
+            self.0.push(id.name);
+        }
+    }
+
+    let mut fields = Fields::default();
+    fields.visit_program(program);
+    fields.0.sort();
+    fields.0.dedup();
+    fields.0
+}
+
+/// The strongly connected components of `program`'s call graph, with every function after the
+/// functions it uses. A function uses another if it mentions its name, whether or not it calls it.
+fn call_graph_components<'ast>(
+    program: &'ast Program,
+    resolution: &Resolution,
+) -> Vec<Vec<&'ast Function>> {
+    struct Uses<'r>(&'r Resolution, Vec<NodeId>);
+
+    impl<'ast> Visitor<'ast> for Uses<'_> {
+        fn visit_ident(&mut self, id: &'ast Ident) {
+            match self.0.binding(id) {
+                Some(binding) if binding.kind == DeclKind::Function => self.1.push(binding.decl),
+                _ => {}
+            }
+        }
+    }
+
+    let mut graph = DiGraph::<&Function, ()>::new();
+    let nodes: NodeMap<_> = program
+        .functions
+        .iter()
+        .map(|f| (f.name.id, graph.add_node(f)))
+        .collect();
+    for f in &program.functions {
+        let mut uses = Uses(resolution, vec![]);
+        visit::walk_function(&mut uses, f);
+        for used in uses.1 {
+            graph.update_edge(nodes[&f.name.id], nodes[&used], ());
+        }
+    }
+    // Tarjan's algorithm finds each component after the components it has edges to.
+    tarjan_scc(&graph)
+        .into_iter()
+        .map(|component| {
+            let mut functions: Vec<_> = component.into_iter().map(|n| graph[n]).collect();
+            functions.sort_by_key(|f| f.span.lo);
+            functions
+        })
+        .collect()
+}
+
+struct Infer<'r> {
+    unifier: Unifier,
+    resolution: &'r Resolution,
+    decls: NodeMap<TypeVar>,
+    exprs: NodeMap<TypeVar>,
+    /// The return type of each function, keyed by the function's id.
+    returns: NodeMap<TypeVar>,
+    /// The functions whose types have been generalised, keyed by the ids of their names.
+    generalised: HashSet<NodeId>,
+    /// Every field name in the program, which every record type has a type for.
+    fields: Vec<Symbol>,
+    /// Values stored in record fields, which can't be records, and where they're stored.
+    field_values: Vec<(TypeVar, Span)>,
+    /// The types of projected fields, which have to be present, and the fields' names and spans.
+    projections: Vec<(TypeVar, Symbol, Span)>,
+    /// The return type of the function being checked.
+    ret: Option<TypeVar>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Infer<'_> {
+    fn decl(&mut self, id: NodeId) -> TypeVar {
+        match self.decls.entry(id) {
+            Entry::Occupied(e) => *e.get(),
+            Entry::Vacant(e) => *e.insert(self.unifier.var()),
+        }
+    }
+
+    fn expr(&self, e: &Expression) -> TypeVar {
+        self.exprs[&e.id]
+    }
+
+    fn int(&mut self, origin: Span) -> TypeVar {
+        self.unifier.term(Term::Int, origin)
+    }
+
+    /// A record type with the given fields, and every other field absent.
+    fn record(&mut self, mut fields: BTreeMap<Symbol, TypeVar>, origin: Span) -> TypeVar {
+        for &name in &self.fields {
+            if let btree_map::Entry::Vacant(e) = fields.entry(name) {
+                e.insert(self.unifier.term(Term::Absent, origin));
+            }
+        }
+        self.unifier.term(Term::Record(fields), origin)
+    }
+
+    /// Requires `a` and `b` to be the same type, because of the code at `span`.
+    fn constrain(&mut self, a: TypeVar, b: TypeVar, span: Span) {
+        let (a, b, field) = match self.unifier.unify(a, b) {
+            Ok(()) => return,
+            Err(Mismatch { left, right, field }) => (left, right, field),
+        };
+        let is_absent = |u: &mut Unifier, v| matches!(u.constructor(v), Term::Absent);
+        let sides = if is_absent(&mut self.unifier, a) {
+            Some((a, b))
+        } else if is_absent(&mut self.unifier, b) {
+            Some((b, a))
+        } else {
+            None
+        };
+        if let Some((absent, present)) = sides {
+            // Outside of a record, an absent type can only have come from projecting a field that
+            // isn't there, which `check_fields` reports.
+            if let Some(field) = field {
+                let mut d = Diagnostic::error(format!("record has no field `{}`", field))
+                    .with_primary(span, "records with different fields");
+                if let Some(origin) = self.unifier.origin(absent) {
+                    d = d.with_secondary(origin, format!("record without `{}`", field));
+                }
+                if let Some(origin) = self.unifier.origin(present) {
+                    d = d.with_secondary(origin, format!("`{}` used here", field));
+                }
+                self.diagnostics.push(d);
+            }
+            return;
+        }
+        let (ta, tb) = (self.unifier.resolve(a), self.unifier.resolve(b));
+        let mut d = Diagnostic::error(format!("mismatched types `{}` and `{}`", ta, tb))
+            .with_primary(span, "types conflict here");
+        for (v, t) in [(a, ta), (b, tb)] {
+            if let Some(origin) = self.unifier.origin(v) {
+                d = d.with_secondary(origin, format!("`{}` because of this", t));
+            }
+        }
+        self.diagnostics.push(d);
+    }
+}
+
+impl Infer<'_> {
+    /// Reports projections of absent fields, and values stored in record fields that are records
+    /// themselves. Only the solved types can tell, so this runs after every constraint has been
+    /// generated.
+    fn check_fields(&mut self) {
+        for (v, name, span) in std::mem::take(&mut self.projections) {
+            if let Term::Absent = self.unifier.constructor(v) {
+                let mut d = Diagnostic::error(format!("record has no field `{}`", name))
+                    .with_primary(span, "no such field");
+                if let Some(origin) = self.unifier.origin(v) {
+                    d = d.with_secondary(origin, format!("record without `{}`", name));
+                }
+                self.diagnostics.push(d);
+            }
+        }
+        for (v, span) in std::mem::take(&mut self.field_values) {
+            if let Term::Record(_) = self.unifier.constructor(v) {
+                let t = self.unifier.resolve(v);
+                self.diagnostics.push(
+                    Diagnostic::error("record fields can't hold records")
+                        .with_primary(span, format!("this is a record, of type `{}`", t))
+                        .with_note("store a pointer to the record instead"),
+                );
+            }
+        }
+    }
+}
+
+impl<'ast> Visitor<'ast> for Infer<'_> {
+    fn visit_function(&mut self, f: &'ast Function) {
+        self.ret = Some(self.returns[&f.id]);
+        visit::walk_function(self, f);
+    }
+
+    fn visit_statement(&mut self, s: &'ast Statement) {
+        visit::walk_statement(self, s);
+        match &s.kind {
+            StatementKind::Assign(lhs, rhs) => {
+                let (l, r) = (self.expr(lhs), self.expr(rhs));
+                self.constrain(l, r, s.span);
+                if let ExpressionKind::Projection(..) = lhs.kind {
+                    self.field_values.push((r, rhs.span));
+                }
+            }
+            StatementKind::If { cond: e, .. }
+            | StatementKind::While { cond: e, .. }
+            | StatementKind::Output(e)
+            | StatementKind::Error(e) => {
+                let (t, int) = (self.expr(e), self.int(s.span));
+                self.constrain(t, int, e.span);
+            }
+            StatementKind::Return(Some(e)) => {
+                let (t, ret) = (self.expr(e), self.ret.unwrap());
+                self.constrain(ret, t, s.span);
+            }
+            StatementKind::VarDecl(ids) => {
+                // Give locals that are never used a type too.
+                for id in ids {
+                    self.decl(id.id);
+                }
+            }
+            StatementKind::Return(None)
+            | StatementKind::ExpressionStatement(_)
+            | StatementKind::Block(_)
+            | StatementKind::Break
+            | StatementKind::Invalid => {}
+        }
+    }
+
+    fn visit_expression(&mut self, e: &'ast Expression) {
+        visit::walk_expression(self, e);
+        let ty = match &e.kind {
+            ExpressionKind::Number(_) | ExpressionKind::Input => self.int(e.span),
+            ExpressionKind::Null => {
+                let pointee = self.unifier.var();
+                self.unifier.term(Term::Pointer(pointee), e.span)
+            }
+            ExpressionKind::IdentReference(id) => match self.resolution.binding(id) {
+                Some(binding) if self.generalised.contains(&binding.decl) => {
+                    let ty = self.decls[&binding.decl];
+                    self.unifier.instantiate(ty)
+                }
+                Some(binding) => self.decl(binding.decl),
+                None => self.unifier.var(),
+            },
+            ExpressionKind::BinaryExpression(op, l, r) => {
+                let (l, r) = (self.expr(l), self.expr(r));
+                if *op == BinOp::CompareEq {
+                    self.constrain(l, r, e.span);
+                } else {
+                    let int = self.int(e.span);
+                    self.constrain(l, int, e.span);
+                    self.constrain(r, int, e.span);
+                }
+                self.int(e.span)
+            }
+            ExpressionKind::UnaryExpression(UnOp::Negate, operand) => {
+                let (t, int) = (self.expr(operand), self.int(e.span));
+                self.constrain(t, int, e.span);
+                int
+            }
+            ExpressionKind::UnaryExpression(UnOp::AddressOf, operand)
+            | ExpressionKind::Alloc(operand) => {
+                let t = self.expr(operand);
+                self.unifier.term(Term::Pointer(t), e.span)
+            }
+            ExpressionKind::UnaryExpression(UnOp::Dereference, operand) => {
+                let (t, pointee) = (self.expr(operand), self.unifier.var());
+                let pointer = self.unifier.term(Term::Pointer(pointee), e.span);
+                self.constrain(t, pointer, e.span);
+                pointee
+            }
+            ExpressionKind::Call(callee, args) => {
+                let callee = self.expr(callee);
+                let args = args.iter().map(|a| self.expr(a)).collect();
+                let ret = self.unifier.var();
+                let f = self.unifier.term(Term::Function(args, ret), e.span);
+                self.constrain(callee, f, e.span);
+                ret
+            }
+            ExpressionKind::Record(fields) => {
+                let fields = fields
+                    .iter()
+                    .map(|(name, value)| {
+                        let t = self.expr(value);
+                        self.field_values.push((t, value.span));
+                        (name.name, t)
+                    })
+                    .collect();
+                self.record(fields, e.span)
+            }
+            ExpressionKind::Projection(record, fields) => {
+                let mut t = self.expr(record);
+                for field in fields {
+                    // A record with the field, and any other fields.
+                    let others = self.fields.clone();
+                    let fields = others
+                        .into_iter()
+                        .map(|name| (name, self.unifier.var()))
+                        .collect::<BTreeMap<_, _>>();
+                    let value = fields[&field.name];
+                    let record = self.unifier.term(Term::Record(fields), e.span);
+                    self.constrain(t, record, field.span);
+                    self.projections.push((value, field.name, field.span));
+                    t = value;
+                }
+                t
+            }
+        };
+        self.exprs.insert(e.id, ty);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resolve;
+    use crate::source_map::SourceMap;
+    use crate::tip_parser;
+
+    /// Infers the types of `src`'s functions, returning them as `name: type` strings, or the
+    /// diagnostics as `line:col: message` strings if there are any.
+    fn check(src: &str) -> Result<Vec<String>, Vec<String>> {
+        check_in(src, Mode::Monomorphic)
+    }
+
+    fn check_in(src: &str, mode: Mode) -> Result<Vec<String>, Vec<String>> {
+        let mut sm = SourceMap::new();
+        let file = sm.add_file("test.tip", src.to_string());
+        let program = tip_parser::parse_file(file).unwrap();
+        let (resolution, errors) = resolve::resolve(&program);
+        assert!(errors.is_empty(), "{:?}", errors);
+        let (types, diagnostics) = infer(&program, &resolution, mode);
+        if !diagnostics.is_empty() {
+            return Err(diagnostics
+                .iter()
+                .map(|d| {
+                    let loc = sm.lookup(d.primary.as_ref().unwrap().span.lo).unwrap();
+                    format!("{}:{}: {}", loc.line, loc.column, d.message)
+                })
+                .collect());
+        }
+        Ok(program
+            .functions
+            .iter()
+            .map(|f| format!("{}: {}", f.name.name, types.decl(&f.name).unwrap()))
+            .collect())
+    }
+
+    #[test]
+    fn test_infer() {
+        assert_eq!(
+            check(
+                "f(p, q) { var r; r = *p + q; *p = r; return &r; }
+                 g(x) { var s; s = {a: x, b: null}; return s.b; }
+                 h(k, n) { return k(n, input); }
+                 id(x) { return x; }"
+            ),
+            Ok(vec![
+                "f: (&int, int) -> &int".to_string(),
+                "g: (α) -> &β".to_string(),
+                "h: ((α, int) -> β, α) -> β".to_string(),
+                "id: (α) -> α".to_string(),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_mismatches() {
+        assert_eq!(
+            check("f(x) { var y; y = 1; y = &x; return y; }"),
+            Err(vec!["1:22: mismatched types `int` and `&α`".to_string()])
+        );
+    }
+
+    #[test]
+    fn test_recursive() {
+        assert_eq!(
+            check(
+                "last(l) { var n; n = l; while (*n == null) { n = *n; } return n; }
+                 node(v) { var n; n = {val: v, next: null}; n = {val: v, next: &n}; return n; }"
+            ),
+            Ok(vec![
+                "last: (μα.&α) -> μα.&α".to_string(),
+                "node: (α) -> μβ.{next: &β, val: α}".to_string(),
+            ])
+        );
+
+        let src = std::fs::read_to_string("examples/shape.tip").unwrap();
+        let program = tip_parser::parse(src).unwrap();
+        let (resolution, _) = resolve::resolve(&program);
+        let (types, diagnostics) = infer(&program, &resolution, Mode::Monomorphic);
+        assert!(diagnostics.is_empty());
+        let vars = match &program.functions[0].body[0].kind {
+            StatementKind::VarDecl(vars) => vars,
+            _ => unreachable!(),
+        };
+        let vars: Vec<_> = vars
+            .iter()
+            .map(|v| format!("{}: {}", v.name, types.decl(v).unwrap()))
+            .collect();
+        assert_eq!(
+            vars,
+            ["x: μα.&α", "y: μα.&α", "n: int", "p: μα.&α", "q: μα.&α"]
+        );
+    }
+
+    #[test]
+    fn test_polymorphic() {
+        let src = "id(x) { return x; }
+                   f() { var a, b; a = id(1); b = id(&a); return id; }
+                   even(n, x) { var r; r = x; if (n > 0) { r = odd(n - 1, x); } return r; }
+                   odd(n, x) { return even(n - 1, x); }
+                   g() { return even(3, 1) + *even(3, alloc 1); }";
+        assert_eq!(
+            check(src),
+            Err(vec![
+                "2:51: mismatched types `int` and `&int`".to_string(),
+                "5:47: mismatched types `int` and `&int`".to_string(),
+            ])
+        );
+        assert_eq!(
+            check_in(src, Mode::Polymorphic),
+            Ok(vec![
+                "id: (α) -> α".to_string(),
+                "f: () -> (α) -> α".to_string(),
+                "even: (int, α) -> α".to_string(),
+                "odd: (int, α) -> α".to_string(),
+                "g: () -> int".to_string(),
+            ])
+        );
+        // Recursive calls within a function's own component are monomorphic.
+        assert_eq!(
+            check_in("f(x) { var y; y = f(1); return f(&y); }", Mode::Polymorphic),
+            Err(vec!["1:32: mismatched types `int` and `&α`".to_string()])
+        );
+    }
+
+    #[test]
+    fn test_records() {
+        assert_eq!(
+            check(
+                "f(p) { var r; r = {a: 1, b: null}; *p = r; return (*p).b; }
+                 g(x) { return x.c; }"
+            ),
+            Ok(vec![
+                "f: (&{a: int, b: &α}) -> &α".to_string(),
+                "g: ({a: α, b: β, c: γ}) -> γ".to_string(),
+            ])
+        );
+        assert_eq!(
+            check("f() { var r, s; s = r.b + 1; r = {a: 1}; r = {b: 2}; return s; }"),
+            Err(vec![
+                "1:30: record has no field `b`".to_string(),
+                "1:42: record has no field `a`".to_string(),
+            ])
+        );
+        assert_eq!(
+            check("f(p) { var r, s; r = {a: 1}; s = {a: r}; (*p).a = r; return 0; }"),
+            Err(vec![
+                "1:38: record fields can't hold records".to_string(),
+                "1:51: record fields can't hold records".to_string(),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_examples() {
+        // The examples with type errors. `ex1` and `ex5` store functions of different types in one
+        // variable, which would need the variable to be polymorphic.
+        let errors = [
+            "err_cmpfunc",
+            "err_cmpfunc2",
+            "err_cmpfunc3",
+            "err_funass",
+            "err_funass2",
+            "err_notlocal2",
+            "err_unify1",
+            "err_unify2",
+            "ex1",
+            "ex5",
+            "record3",
+            "record5",
+            "record6",
+        ];
+        let mut failed = vec![];
+        let mut failed_polymorphic = vec![];
+        for entry in std::fs::read_dir("examples").unwrap() {
+            let path = entry.unwrap().path();
+            let name = path.file_stem().unwrap().to_str().unwrap().to_string();
+            let src = std::fs::read_to_string(&path).unwrap();
+            let mut sm = SourceMap::new();
+            let file = sm.add_file("test.tip", src);
+            let program = tip_parser::parse_file(file).unwrap();
+            let (resolution, errors) = resolve::resolve(&program);
+            if !errors.is_empty() {
+                continue;
+            }
+            if !infer(&program, &resolution, Mode::Polymorphic).1.is_empty() {
+                failed_polymorphic.push(name.clone());
+            }
+            if !infer(&program, &resolution, Mode::Monomorphic).1.is_empty() {
+                failed.push(name);
+            }
+        }
+        failed.sort();
+        assert_eq!(failed, errors);
+        failed_polymorphic.sort();
+        assert_eq!(failed_polymorphic, errors);
+    }
+}
@@ -0,0 +1,175 @@
+//! Inferred types in a form for people to read: a listing of each function's types, and the source
+//! annotated with them.
+
+use super::{Type, Types, VarNames};
+use crate::ast::visit::{self, Visitor};
+use crate::ast::{Function, Ident, Program, Statement, StatementKind};
+use crate::source_map::SourceFile;
+use std::fmt::Write;
+
+/// Each function's type, followed by the types of its parameters and locals, one per line.
+///
+/// Type variables are named per function, so an `α` in a function's type is the same type as an
+/// `α` in the types of its variables.
+pub fn dump(program: &Program, types: &Types) -> String {
+    let mut out = String::new();
+    for f in &program.functions {
+        let mut names = VarNames::default();
+        writeln!(
+            out,
+            "{}: {}",
+            f.name.name,
+            describe(types.decl(&f.name), &mut names)
+        )
+        .unwrap();
+        for v in variables(f) {
+            writeln!(
+                out,
+                "    {}: {}",
+                v.name,
+                describe(types.decl(v), &mut names)
+            )
+            .unwrap();
+        }
+    }
+    out
+}
+
+/// The source of `file`, with a `/* : type */` comment after each parameter and local giving its
+/// type, and one after each function's parameter list giving the function's type.
+pub fn annotate(file: &SourceFile, program: &Program, types: &Types) -> String {
+    let mut annotations = vec![];
+    for f in &program.functions {
+        if !file.contains(f.span.lo) {
+            continue;
+        }
+        let mut names = VarNames::default();
+        let params_end = params_end(file, f.params.last().unwrap_or(&f.name).span.hi);
+        annotations.push((params_end, describe(types.decl(&f.name), &mut names)));
+        for v in variables(f) {
+            annotations.push((v.span.hi, describe(types.decl(v), &mut names)));
+        }
+    }
+    annotations.sort_by_key(|(pos, _)| *pos);
+
+    let mut out = String::new();
+    let mut copied = 0;
+    for (pos, t) in annotations {
+        let pos = pos - file.start_pos;
+        out.push_str(&file.src[copied..pos]);
+        write!(out, " /* : {} */", t).unwrap();
+        copied = pos;
+    }
+    out.push_str(&file.src[copied..]);
+    out
+}
+
+fn describe(t: Option<&Type>, names: &mut VarNames) -> String {
+    t.map_or_else(|| "?".to_string(), |t| t.to_string_with(names))
+}
+
+/// The position just after the `)` that ends a function's parameter list, given the end of its last
+/// parameter or, if it has none, its name. Falls back to `pos` if the text isn't as expected.
+fn params_end(file: &SourceFile, pos: usize) -> usize {
+    let src = &file.src[pos - file.start_pos..];
+    let mut rest = src;
+    loop {
+        rest = rest.trim_start();
+        if let Some(comment) = rest.strip_prefix("//") {
+            rest = comment.find('\n').map_or("", |end| &comment[end..]);
+        } else if let Some(comment) = rest.strip_prefix("/*") {
+            rest = comment.find("*/").map_or("", |end| &comment[end + 2..]);
+        } else if let Some(after) = rest.strip_prefix('(') {
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix(')') {
+            return pos + (src.len() - after.len());
+        } else {
+            return pos;
+        }
+    }
+}
+
+/// The variables a function declares: its parameters, then its locals.
+fn variables(f: &Function) -> Vec<&Ident> {
+    struct Locals<'ast>(Vec<&'ast Ident>);
+
+    impl<'ast> Visitor<'ast> for Locals<'ast> {
+        fn visit_statement(&mut self, s: &'ast Statement) {
+            if let StatementKind::VarDecl(ids) = &s.kind {
+                self.0.extend(ids);
+            }
+            visit::walk_statement(self, s)
+        }
+    }
+
+    let mut locals = Locals(f.params.iter().collect());
+    for s in &f.body {
+        locals.visit_statement(s);
+    }
+    locals.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resolve;
+    use crate::source_map::SourceMap;
+    use crate::tip_parser;
+    use crate::types::{infer, Mode};
+
+    const SRC: &str = "\
+deref(p /* a pointer */) {
+    var x;
+    x = *p;
+    return x;
+}
+
+main() {
+    var n, q;
+    n = alloc null;
+    *n = n;
+    q = deref(&n);
+    return 0;
+}
+";
+
+    #[test]
+    fn test_dump_and_annotate() {
+        let mut sm = SourceMap::new();
+        let file = sm.add_file("test.tip", SRC.to_string());
+        let program = tip_parser::parse_file(file).unwrap();
+        let (resolution, _) = resolve::resolve(&program);
+        let (types, diagnostics) = infer(&program, &resolution, Mode::Polymorphic);
+        assert!(diagnostics.is_empty());
+
+        assert_eq!(
+            dump(&program, &types),
+            "\
+deref: (&α) -> α
+    p: &α
+    x: α
+main: () -> int
+    n: μα.&α
+    q: μα.&α
+"
+        );
+        assert_eq!(
+            annotate(file, &program, &types),
+            "\
+deref(p /* : &α */ /* a pointer */) /* : (&α) -> α */ {
+    var x /* : α */;
+    x = *p;
+    return x;
+}
+
+main() /* : () -> int */ {
+    var n /* : μα.&α */, q /* : μα.&α */;
+    n = alloc null;
+    *n = n;
+    q = deref(&n);
+    return 0;
+}
+"
+        );
+    }
+}
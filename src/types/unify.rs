@@ -0,0 +1,286 @@
+//! Union-find unification of type terms.
+
+use super::Type;
+use crate::source_map::Span;
+use crate::symbol::Symbol;
+use std::collections::{BTreeMap, HashMap};
+
+/// A type in the unifier's table. Two variables denote the same type once they've been unified.
+///
+/// The table is a graph rather than a tree: unifying a variable with a type that contains it makes
+/// a cycle, which stands for a recursive type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(super) struct TypeVar(u32);
+
+/// The constructor of a type, with the types it's built from.
+#[derive(Debug, Clone)]
+pub(super) enum Term {
+    /// Not known yet.
+    Var,
+    Int,
+    Pointer(TypeVar),
+    Function(Vec<TypeVar>, TypeVar),
+    /// A record, with the type of every field in the program. Fields the record doesn't have are
+    /// `Absent`.
+    Record(BTreeMap<Symbol, TypeVar>),
+    /// The type of a field that a record doesn't have.
+    Absent,
+}
+
+struct Node {
+    parent: TypeVar,
+    term: Term,
+    /// The code that gave this type its constructor, if it has one.
+    origin: Option<Span>,
+}
+
+/// Two types couldn't be unified because they have different constructors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) struct Mismatch {
+    /// The innermost parts of the two types that conflict.
+    pub left: TypeVar,
+    pub right: TypeVar,
+    /// The record field that `left` and `right` are the types of, if they're inside records.
+    pub field: Option<Symbol>,
+}
+
+#[derive(Default)]
+pub(super) struct Unifier {
+    nodes: Vec<Node>,
+}
+
+impl Unifier {
+    /// A fresh type, not equal to any other.
+    pub(super) fn var(&mut self) -> TypeVar {
+        self.push(Term::Var, None)
+    }
+
+    /// A fresh type with the constructor `term`, given to it by the code at `origin`.
+    pub(super) fn term(&mut self, term: Term, origin: Span) -> TypeVar {
+        self.push(term, Some(origin))
+    }
+
+    fn push(&mut self, term: Term, origin: Option<Span>) -> TypeVar {
+        let v = TypeVar(self.nodes.len() as u32);
+        self.nodes.push(Node {
+            parent: v,
+            term,
+            origin,
+        });
+        v
+    }
+
+    fn node(&self, v: TypeVar) -> &Node {
+        &self.nodes[v.0 as usize]
+    }
+
+    /// The representative of the set of types equal to `v`.
+    pub(super) fn find(&mut self, v: TypeVar) -> TypeVar {
+        let mut root = v;
+        while self.node(root).parent != root {
+            root = self.node(root).parent;
+        }
+        let mut v = v;
+        while v != root {
+            let next = self.node(v).parent;
+            self.nodes[v.0 as usize].parent = root;
+            v = next;
+        }
+        root
+    }
+
+    /// The constructor of `v`'s type.
+    pub(super) fn constructor(&mut self, v: TypeVar) -> &Term {
+        let root = self.find(v);
+        &self.node(root).term
+    }
+
+    /// Where the constructor of `v`'s type came from.
+    pub(super) fn origin(&mut self, v: TypeVar) -> Option<Span> {
+        let root = self.find(v);
+        self.node(root).origin
+    }
+
+    /// Makes `a` and `b` the same type. On failure the types may be partially unified.
+    ///
+    /// Two types are merged before their parts are unified, so unifying recursive types stops when
+    /// it comes back around to a pair of types it has already merged.
+    pub(super) fn unify(&mut self, a: TypeVar, b: TypeVar) -> Result<(), Mismatch> {
+        let a = self.find(a);
+        let b = self.find(b);
+        if a == b {
+            return Ok(());
+        }
+        match (&self.node(a).term, &self.node(b).term) {
+            (Term::Var, _) => self.union(a, b),
+            (_, Term::Var) => return self.unify(b, a),
+            (Term::Int, Term::Int) | (Term::Absent, Term::Absent) => self.union(a, b),
+            (&Term::Pointer(x), &Term::Pointer(y)) => {
+                self.union(a, b);
+                self.unify(x, y)?;
+            }
+            (Term::Function(p1, r1), Term::Function(p2, r2)) if p1.len() == p2.len() => {
+                let pairs: Vec<_> = p1.iter().copied().zip(p2.iter().copied()).collect();
+                let (r1, r2) = (*r1, *r2);
+                self.union(a, b);
+                for (x, y) in pairs {
+                    self.unify(x, y)?;
+                }
+                self.unify(r1, r2)?;
+            }
+            (Term::Record(f1), Term::Record(f2)) => {
+                debug_assert!(f1.keys().eq(f2.keys()));
+                let pairs: Vec<_> = f1
+                    .iter()
+                    .zip(f2.values())
+                    .map(|((&name, &x), &y)| (name, x, y))
+                    .collect();
+                self.union(a, b);
+                for (name, x, y) in pairs {
+                    self.unify(x, y).map_err(|mut m| {
+                        m.field.get_or_insert(name);
+                        m
+                    })?;
+                }
+            }
+            _ => {
+                return Err(Mismatch {
+                    left: a,
+                    right: b,
+                    field: None,
+                })
+            }
+        }
+        Ok(())
+    }
+
+    /// A copy of the type `v` with fresh type variables in place of its type variables, so that
+    /// unifying the copy with other types leaves `v` alone.
+    pub(super) fn instantiate(&mut self, v: TypeVar) -> TypeVar {
+        self.copy(v, &mut HashMap::new())
+    }
+
+    fn copy(&mut self, v: TypeVar, copies: &mut HashMap<TypeVar, TypeVar>) -> TypeVar {
+        let v = self.find(v);
+        if let Some(&copy) = copies.get(&v) {
+            return copy;
+        }
+        // Record the copy before copying the parts of `v`, so that the copy of a recursive type
+        // refers back to itself.
+        let copy = self.push(Term::Var, self.node(v).origin);
+        copies.insert(v, copy);
+        let term = match self.node(v).term.clone() {
+            Term::Var => Term::Var,
+            Term::Int => Term::Int,
+            Term::Absent => Term::Absent,
+            Term::Pointer(x) => Term::Pointer(self.copy(x, copies)),
+            Term::Function(params, ret) => Term::Function(
+                params.into_iter().map(|p| self.copy(p, copies)).collect(),
+                self.copy(ret, copies),
+            ),
+            Term::Record(fields) => Term::Record(
+                fields
+                    .into_iter()
+                    .map(|(name, x)| (name, self.copy(x, copies)))
+                    .collect(),
+            ),
+        };
+        self.nodes[copy.0 as usize].term = term;
+        copy
+    }
+
+    /// Makes `b` the representative of `a`'s set.
+    fn union(&mut self, a: TypeVar, b: TypeVar) {
+        self.nodes[a.0 as usize].parent = b;
+    }
+
+    /// The type `v` has been unified with so far. Type variables that haven't been unified with
+    /// anything are numbered by their representative, so they're the same across calls, and so
+    /// are the variables bound by recursive types.
+    pub(super) fn resolve(&mut self, v: TypeVar) -> Type {
+        self.resolve_within(v, &mut vec![])
+    }
+
+    /// Resolves `v`, which is part of each of the types in `enclosing`. Those types are marked as
+    /// recursive if `v` turns out to be one of them.
+    fn resolve_within(&mut self, v: TypeVar, enclosing: &mut Vec<(TypeVar, bool)>) -> Type {
+        let v = self.find(v);
+        if let Some((_, recursive)) = enclosing.iter_mut().find(|(t, _)| *t == v) {
+            *recursive = true;
+            return Type::Var(v.0);
+        }
+        enclosing.push((v, false));
+        let t = match self.node(v).term.clone() {
+            Term::Var => Type::Var(v.0),
+            Term::Int => Type::Int,
+            Term::Absent => Type::Absent,
+            Term::Pointer(x) => Type::Pointer(Box::new(self.resolve_within(x, enclosing))),
+            Term::Function(params, ret) => Type::Function(
+                params
+                    .into_iter()
+                    .map(|p| self.resolve_within(p, enclosing))
+                    .collect(),
+                Box::new(self.resolve_within(ret, enclosing)),
+            ),
+            Term::Record(fields) => {
+                let mut fields: Vec<_> = fields
+                    .into_iter()
+                    .map(|(name, x)| (name, self.resolve_within(x, enclosing)))
+                    .filter(|(_, t)| *t != Type::Absent)
+                    .collect();
+                fields.sort_by_key(|(name, _)| name.as_str());
+                Type::Record(fields)
+            }
+        };
+        match enclosing.pop() {
+            Some((_, true)) => Type::Recursive(v.0, Box::new(t)),
+            _ => t,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unify() {
+        let mut u = Unifier::default();
+        let span = Span::DUMMY;
+        let (a, b) = (u.var(), u.var());
+        let int = u.term(Term::Int, span);
+        let f = u.term(Term::Function(vec![a], b), span);
+        let g = u.term(Term::Function(vec![int], a), span);
+        assert_eq!(u.unify(f, g), Ok(()));
+        assert_eq!(
+            u.resolve(f),
+            Type::Function(vec![Type::Int], Box::new(Type::Int))
+        );
+
+        let c = u.var();
+        let p = u.term(Term::Pointer(c), span);
+        assert!(u.unify(p, int).is_err());
+        let h = u.term(Term::Function(vec![], c), span);
+        assert!(u.unify(f, h).is_err());
+    }
+
+    #[test]
+    fn test_recursive() {
+        let mut u = Unifier::default();
+        let span = Span::DUMMY;
+        // α = &α and β = &&β are the same type, and unifying them terminates.
+        let (a, b, c) = (u.var(), u.var(), u.var());
+        let pa = u.term(Term::Pointer(a), span);
+        let pb = u.term(Term::Pointer(b), span);
+        let ppb = u.term(Term::Pointer(pb), span);
+        assert_eq!(u.unify(a, pa), Ok(()));
+        assert_eq!(u.unify(c, ppb), Ok(()));
+        assert_eq!(u.unify(b, c), Ok(()));
+        assert_eq!(u.unify(a, b), Ok(()));
+        let root = u.find(a).0;
+        assert_eq!(
+            u.resolve(b),
+            Type::Recursive(root, Box::new(Type::Pointer(Box::new(Type::Var(root)))))
+        );
+    }
+}
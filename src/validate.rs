@@ -0,0 +1,343 @@
+//! Structural checks that the grammar is too permissive to make.
+//!
+//! The parser accepts statements in any order, but TIP requires a function body to be its `var`
+//! declarations, then its other statements, then a single `return`. `break` is only allowed inside
+//! a `while`, and only variables, dereferences and record fields can be assigned to. Functions
+//! can't be assigned to or have their address taken, and calls to them must pass as many arguments
+//! as they have parameters, which needs the result of name resolution.
+
+use crate::ast::visit::{self, Visitor};
+use crate::ast::{
+    Expression, ExpressionKind, Function, NodeMap, Program, Statement, StatementKind, UnOp,
+};
+use crate::diagnostic::{Diagnostic, Severity};
+use crate::resolve::{Binding, DeclKind, Resolution};
+use crate::source_map::Span;
+
+/// Checks `program` against TIP's structural rules, returning a diagnostic for each violation.
+pub fn validate(program: &Program, resolution: &Resolution) -> Vec<Diagnostic> {
+    let mut validator = Validator {
+        resolution,
+        arity: program
+            .functions
+            .iter()
+            .map(|f| (f.name.id, f.params.len()))
+            .collect(),
+        loops: 0,
+        diagnostics: vec![],
+    };
+    validator.visit_program(program);
+    validator.diagnostics
+}
+
+/// Checks that `program` has a `main` function to start from, and notes that `main`'s parameters,
+/// if it has any, are read from the program's input. A program with several functions called
+/// `main` is already rejected by name resolution.
+pub fn check_main(program: &Program) -> Vec<Diagnostic> {
+    let main = match program.functions.iter().find(|f| f.name.name == "main") {
+        Some(main) => main,
+        None => {
+            return vec![Diagnostic::error("no `main` function")
+                .with_note("a program starts by calling `main`, so it must define one")]
+        }
+    };
+    match (main.params.first(), main.params.last()) {
+        (Some(first), Some(last)) => vec![Diagnostic::new(
+            Severity::Note,
+            format!(
+                "`main` takes {}, which are read from the program's input",
+                plural(main.params.len(), "parameter")
+            ),
+        )
+        .with_primary(first.span.to(last.span), "program inputs")],
+        _ => vec![],
+    }
+}
+
+fn plural(n: usize, noun: &str) -> String {
+    if n == 1 {
+        format!("{} {}", n, noun)
+    } else {
+        format!("{} {}s", n, noun)
+    }
+}
+
+struct Validator<'r> {
+    resolution: &'r Resolution,
+    /// The number of parameters of each function, keyed by its name's id.
+    arity: NodeMap<usize>,
+    /// How many `while` loops enclose the statement being checked.
+    loops: usize,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Validator<'_> {
+    /// The function `e` names, if it's a reference to one.
+    fn function_reference(&self, e: &Expression) -> Option<Binding> {
+        match &e.kind {
+            ExpressionKind::IdentReference(id) => self
+                .resolution
+                .binding(id)
+                .filter(|b| b.kind == DeclKind::Function),
+            _ => None,
+        }
+    }
+
+    fn check_lvalue(&mut self, lhs: &Expression) {
+        match &lhs.kind {
+            ExpressionKind::IdentReference(id) => {
+                if let Some(function) = self.function_reference(lhs) {
+                    self.diagnostics.push(
+                        Diagnostic::error(format!("cannot assign to function `{}`", id.name))
+                            .with_primary(lhs.span, "assigned to here")
+                            .with_secondary(function.span, "function defined here"),
+                    );
+                }
+            }
+            ExpressionKind::UnaryExpression(UnOp::Dereference, _) => {}
+            ExpressionKind::Projection(record, _) => self.check_lvalue(record),
+            _ => self.diagnostics.push(
+                Diagnostic::error("invalid left-hand side of assignment")
+                    .with_primary(lhs.span, "cannot assign to this")
+                    .with_note("only variables, dereferences and record fields can be assigned to"),
+            ),
+        }
+    }
+
+    fn check_call(&mut self, callee: &Expression, args: usize, span: Span) {
+        if let (ExpressionKind::IdentReference(id), Some(function)) =
+            (&callee.kind, self.function_reference(callee))
+        {
+            let params = self.arity[&function.decl];
+            if params != args {
+                self.diagnostics.push(
+                    Diagnostic::error(format!(
+                        "`{}` takes {} but {} supplied",
+                        id.name,
+                        plural(params, "argument"),
+                        if args == 1 {
+                            "1 was".to_string()
+                        } else {
+                            format!("{} were", args)
+                        }
+                    ))
+                    .with_primary(span, format!("called with {}", plural(args, "argument")))
+                    .with_secondary(function.span, "function defined here"),
+                );
+            }
+            return;
+        }
+        // Variables, dereferences, projections and calls may all evaluate to a function, but
+        // nothing else can.
+        match &callee.kind {
+            ExpressionKind::IdentReference(_)
+            | ExpressionKind::UnaryExpression(UnOp::Dereference, _)
+            | ExpressionKind::Projection(..)
+            | ExpressionKind::Call(..) => {}
+            _ => self.diagnostics.push(
+                Diagnostic::error("called value isn't a function")
+                    .with_primary(callee.span, "not a function"),
+            ),
+        }
+    }
+}
+
+impl<'ast> Visitor<'ast> for Validator<'_> {
+    fn visit_function(&mut self, f: &'ast Function) {
+        // Statements in the function body are passed to `visit_statement` unless they're in the
+        // right place, so that `visit_statement` only sees misplaced declarations and returns.
+        let mut in_declarations = true;
+        for (idx, s) in f.body.iter().enumerate() {
+            match &s.kind {
+                StatementKind::VarDecl(_) if in_declarations => continue,
+                StatementKind::Return(e) if idx + 1 == f.body.len() => {
+                    if let Some(e) = e {
+                        self.visit_expression(e);
+                    }
+                    continue;
+                }
+                _ => {}
+            }
+            in_declarations = false;
+            self.visit_statement(s);
+        }
+        match f.body.last().map(|s| &s.kind) {
+            Some(StatementKind::Return(_)) | Some(StatementKind::Invalid) => {}
+            _ => self.diagnostics.push(
+                Diagnostic::error(format!(
+                    "function `{}` doesn't end with `return`",
+                    f.name.name
+                ))
+                .with_primary(f.name.span, "this function")
+                .with_note("the last statement of every function must be a `return`"),
+            ),
+        }
+    }
+
+    fn visit_statement(&mut self, s: &'ast Statement) {
+        match &s.kind {
+            StatementKind::VarDecl(_) => self.diagnostics.push(
+                Diagnostic::error("variables must be declared at the start of a function")
+                    .with_primary(s.span, "declared after other statements"),
+            ),
+            StatementKind::Return(_) => self.diagnostics.push(
+                Diagnostic::error("`return` must be the last statement of a function")
+                    .with_primary(s.span, "returns before the end of the function"),
+            ),
+            StatementKind::Break if self.loops == 0 => self.diagnostics.push(
+                Diagnostic::error("`break` outside of a `while` loop")
+                    .with_primary(s.span, "cannot break out of here"),
+            ),
+            StatementKind::While { .. } => {
+                self.loops += 1;
+                visit::walk_statement(self, s);
+                self.loops -= 1;
+                return;
+            }
+            StatementKind::Assign(lhs, _) => self.check_lvalue(lhs),
+            _ => {}
+        }
+        visit::walk_statement(self, s)
+    }
+
+    fn visit_expression(&mut self, e: &'ast Expression) {
+        match &e.kind {
+            ExpressionKind::UnaryExpression(UnOp::AddressOf, operand) => {
+                if let Some(function) = self.function_reference(operand) {
+                    self.diagnostics.push(
+                        Diagnostic::error("cannot take the address of a function")
+                            .with_primary(e.span, "address taken here")
+                            .with_secondary(function.span, "function defined here"),
+                    );
+                }
+            }
+            ExpressionKind::Call(callee, args) => self.check_call(callee, args.len(), e.span),
+            _ => {}
+        }
+        visit::walk_expression(self, e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resolve;
+    use crate::source_map::SourceMap;
+    use crate::tip_parser;
+
+    /// Validates `src`, returning the diagnostics as `line:col: message` strings.
+    fn check(src: &str) -> Vec<String> {
+        let mut sm = SourceMap::new();
+        let file = sm.add_file("test.tip", src.to_string());
+        let program = tip_parser::parse_file(file).unwrap();
+        let (resolution, _) = resolve::resolve(&program);
+        validate(&program, &resolution)
+            .iter()
+            .map(|d| {
+                let loc = sm.lookup(d.primary.as_ref().unwrap().span.lo).unwrap();
+                format!("{}:{}: {}", loc.line, loc.column, d.message)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_statement_order() {
+        assert_eq!(
+            check("f(x) { var a; a = x; var b; if (x) { var c; return a; } return a; output a; }"),
+            [
+                "1:22: variables must be declared at the start of a function",
+                "1:38: variables must be declared at the start of a function",
+                "1:45: `return` must be the last statement of a function",
+                "1:57: `return` must be the last statement of a function",
+                "1:1: function `f` doesn't end with `return`",
+            ]
+        );
+        assert_eq!(
+            check("f() { }"),
+            ["1:1: function `f` doesn't end with `return`"]
+        );
+        assert!(check("f() { var a; var b; a = 1; return; }").is_empty());
+    }
+
+    #[test]
+    fn test_break() {
+        assert_eq!(
+            check("f(x) { while (x) { if (x) { break; } } if (x) { break; } return x; }"),
+            ["1:49: `break` outside of a `while` loop"]
+        );
+    }
+
+    #[test]
+    fn test_lvalues() {
+        assert_eq!(
+            check("f(x) { x = 1; *x = 1; x.a = 1; (*x).a.b = 1; x + 1 = 2; g().a = 1; return x; }"),
+            [
+                "1:46: invalid left-hand side of assignment",
+                "1:57: invalid left-hand side of assignment",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_calls() {
+        assert_eq!(
+            check(
+                "f(a, b) { return g(a, b) + f(a, b, 1) + f(a, b); } \
+                 g(x) { return x(1, 2) + (*x)() + 1(x) + (x + 1)(); }"
+            ),
+            [
+                "1:18: `g` takes 1 argument but 2 were supplied",
+                "1:28: `f` takes 2 arguments but 3 were supplied",
+                "1:85: called value isn't a function",
+                "1:92: called value isn't a function",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_main() {
+        let check_main = |src: &str| {
+            let program = tip_parser::parse(src.to_string()).unwrap();
+            check_main(&program)
+                .into_iter()
+                .map(|d| (d.severity, d.message, d.primary.map(|l| l.span)))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            check_main("f() { return 0; }"),
+            [(Severity::Error, "no `main` function".to_string(), None)]
+        );
+        assert!(check_main("f() { return 0; } main() { return f(); }").is_empty());
+        assert_eq!(
+            check_main("main(x, y) { return x; }"),
+            [(
+                Severity::Note,
+                "`main` takes 2 parameters, which are read from the program's input".to_string(),
+                Some(Span::new(5, 9))
+            )]
+        );
+    }
+
+    #[test]
+    fn test_examples() {
+        // Examples rejected by these checks; every other example passes them.
+        let table: &[(&str, &[&str])] = &[
+            ("err_assignfunc", &["10:5: cannot assign to function `f`"]),
+            ("err_funass", &["5:5: cannot assign to function `main`"]),
+            (
+                "err_funass2",
+                &["5:16: cannot take the address of a function"],
+            ),
+        ];
+        for entry in std::fs::read_dir("examples").unwrap() {
+            let path = entry.unwrap().path();
+            let name = path.file_stem().unwrap().to_str().unwrap();
+            let expected = table
+                .iter()
+                .find(|(n, _)| *n == name)
+                .map_or(&[][..], |(_, e)| e);
+            let src = std::fs::read_to_string(&path).unwrap();
+            assert_eq!(check(&src), expected, "validating {}", name);
+        }
+    }
+}
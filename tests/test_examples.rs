@@ -1,6 +1,8 @@
 #[cfg(test)]
 mod tests {
     use std::path::Path;
+    use tip::pretty;
+    use tip::source_map::SourceMap;
     use tip::tip_parser;
     #[test]
     fn test_examples_folder() {
@@ -17,6 +19,80 @@ mod tests {
             }
             Ok(())
         }
-        let _ = try_parse_dir(Path::new("examples")).unwrap();
+        try_parse_dir(Path::new("examples")).unwrap();
+    }
+
+    #[test]
+    fn test_examples_folder_recovering() {
+        for path in std::fs::read_dir("examples").unwrap().map(|e| e.unwrap().path()) {
+            let mut sm = SourceMap::new();
+            let src = std::fs::read_to_string(&path).unwrap();
+            let file = sm.add_file(path.display().to_string(), src);
+            let (program, errors) = tip_parser::parse_file_recovering(file);
+            assert!(errors.is_empty(), "{:?} has syntax errors: {:?}", path, errors);
+            assert_eq!(Ok(program), tip_parser::parse_file(file));
+        }
+    }
+
+    #[test]
+    fn test_examples_round_trip() {
+        for path in std::fs::read_dir("examples").unwrap().map(|e| e.unwrap().path()) {
+            let program = tip_parser::parse(std::fs::read_to_string(&path).unwrap()).unwrap();
+            let printed = program.to_string();
+            let reparsed = tip_parser::parse(printed.clone()).unwrap_or_else(|e| {
+                panic!("{:?} printed as invalid source: {}\n{}", path, e, printed)
+            });
+            assert_eq!(
+                program, reparsed,
+                "{:?} changed when printed as:\n{}",
+                path, printed
+            );
+            // Printing is idempotent, too.
+            assert_eq!(printed, reparsed.to_string());
+        }
+    }
+
+    #[test]
+    fn test_examples_format_keeps_comments() {
+        fn comments(src: &str) -> Vec<String> {
+            let mut sm = SourceMap::new();
+            let file = sm.add_file("test.tip", src.replace("\r\n", "\n"));
+            let (_, trivia) = tip_parser::parse_file_with_trivia(file).unwrap();
+            let mut comments: Vec<_> = trivia
+                .leading
+                .values()
+                .chain(trivia.trailing.values())
+                .chain(trivia.body_start.values())
+                .chain(trivia.body_end.values())
+                .chain(std::iter::once(&trivia.end))
+                .flatten()
+                .map(|c| c.text.trim_end().to_string())
+                .collect();
+            comments.sort();
+            comments
+        }
+
+        for path in std::fs::read_dir("examples").unwrap().map(|e| e.unwrap().path()) {
+            let src = std::fs::read_to_string(&path).unwrap();
+            let mut sm = SourceMap::new();
+            let file = sm.add_file(path.display().to_string(), src.clone());
+            let formatted = pretty::format_file(file).unwrap();
+            assert_eq!(
+                tip_parser::parse(src.clone()).unwrap(),
+                tip_parser::parse(formatted.clone()).unwrap(),
+                "{:?} changed when formatted as:\n{}",
+                path,
+                formatted
+            );
+            assert_eq!(
+                comments(&src),
+                comments(&formatted),
+                "{:?} lost comments when formatted as:\n{}",
+                path,
+                formatted
+            );
+            let file = sm.add_file("formatted.tip", formatted.clone());
+            assert_eq!(pretty::format_file(file).unwrap(), formatted);
+        }
     }
 }